@@ -1,7 +1,9 @@
 use crate::common::{Direction, Grid, Vec2i};
 use aoc_runner_derive::{aoc, aoc_generator};
 use itertools::Itertools;
-use rustc_hash::FxHashSet;
+use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::cmp::Reverse;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Tile {
@@ -30,9 +32,8 @@ pub fn input_generator(input: &str) -> Grid<Tile> {
     input.parse().unwrap()
 }
 
-fn get_path(grid: &Grid<Tile>) -> (FxHashSet<(Vec2i, Direction)>, bool) {
-    let (start_pos, start_dir) = grid
-        .pos_iter()
+fn find_guard(grid: &Grid<Tile>) -> (Vec2i, Direction) {
+    grid.pos_iter()
         .find_map(|(pos, tile)| {
             if let Tile::Guard(dir) = tile {
                 Some((pos, *dir))
@@ -40,21 +41,112 @@ fn get_path(grid: &Grid<Tile>) -> (FxHashSet<(Vec2i, Direction)>, bool) {
                 None
             }
         })
-        .unwrap();
+        .unwrap()
+}
+
+fn dir_index(dir: Direction) -> usize {
+    match dir {
+        Direction::North => 0,
+        Direction::East => 1,
+        Direction::South => 2,
+        Direction::West => 3,
+    }
+}
+
+/// For every cell and direction the last cell the guard reaches before walking into an obstacle,
+/// `None` if it leaves the map instead.
+struct JumpTable {
+    next: Grid<[Option<Vec2i>; 4]>,
+}
+
+impl JumpTable {
+    fn new(grid: &Grid<Tile>) -> Self {
+        let mut next = Grid::new_from_element(grid.size_x, grid.size_y, [None; 4]);
+        for dir in Direction::VALUES {
+            let i = dir_index(dir);
+            // cells further along the direction are done first, so their entry is ready
+            for pos in grid
+                .pos_iter()
+                .map(|(pos, _)| pos)
+                .sorted_unstable_by_key(|pos| Reverse(dir.vec().dot(pos)))
+            {
+                let target = dir.offset(&pos);
+                next[pos][i] = if !grid.in_bounds(&target) {
+                    None
+                } else if grid[target] == Tile::Obstacle {
+                    Some(pos)
+                } else {
+                    next[target][i]
+                };
+            }
+        }
+
+        Self { next }
+    }
+
+    /// Like the table lookup, but with an additional obstacle placed at `obstacle`.
+    ///
+    /// Only the line from `pos` towards `dir` is affected by the new obstacle, so the table itself stays untouched.
+    fn jump(&self, pos: Vec2i, dir: Direction, obstacle: Vec2i) -> Option<Vec2i> {
+        let stop = self.next[pos][dir_index(dir)];
+        let v = dir.vec();
+        let to_obstacle = obstacle - pos;
+        let distance = to_obstacle.dot(&v);
+        if distance > 0
+            && to_obstacle == distance * v
+            && stop.is_none_or(|stop| distance <= (stop - pos).dot(&v))
+        {
+            Some(obstacle - v)
+        } else {
+            stop
+        }
+    }
+
+    /// Whether the guard starting at `pos` facing `dir` loops with an additional obstacle at `obstacle`.
+    fn is_loop(&self, mut pos: Vec2i, mut dir: Direction, obstacle: Vec2i) -> bool {
+        let mut turns = FxHashSet::default();
+        loop {
+            if !turns.insert((pos, dir)) {
+                // also covers getting stuck between four obstacles
+                return true;
+            }
+
+            let Some(stop) = self.jump(pos, dir, obstacle) else {
+                return false;
+            };
+            pos = stop;
+            dir = dir.rotate_cw();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Route {
+    /// every step of the guard with the direction it was moving in, starting with the initial position
+    pub steps: Vec<(Vec2i, Direction)>,
+    /// index of the first step of the cycle if the guard never leaves the map
+    pub cycle_start: Option<usize>,
+}
+
+pub fn get_path(grid: &Grid<Tile>) -> Route {
+    let (start_pos, start_dir) = find_guard(grid);
 
     let mut pos = start_pos;
     let mut dir = start_dir;
-    let mut all_pos = FxHashSet::default();
-    let out_of_bounds = 'outer: loop {
-        if !all_pos.insert((pos, dir)) {
-            break 'outer false;
+    let mut steps = vec![];
+    let mut indices = FxHashMap::default();
+    let cycle_start = 'outer: loop {
+        if let Some(&i) = indices.get(&(pos, dir)) {
+            break 'outer Some(i);
         }
+        indices.insert((pos, dir), steps.len());
+        steps.push((pos, dir));
 
         let initial_dir = dir;
         loop {
             let target = dir.offset(&pos);
             if !grid.in_bounds(&target) {
-                break 'outer true;
+                break 'outer None;
             }
 
             if grid[target] == Tile::Obstacle {
@@ -62,7 +154,7 @@ fn get_path(grid: &Grid<Tile>) -> (FxHashSet<(Vec2i, Direction)>, bool) {
                 if dir == initial_dir {
                     // we got stuck, can only happen if start pos is surrounded by obstacles
                     // -> treat this as a loop
-                    break 'outer false;
+                    break 'outer Some(steps.len() - 1);
                 }
             } else {
                 pos = target;
@@ -71,36 +163,112 @@ fn get_path(grid: &Grid<Tile>) -> (FxHashSet<(Vec2i, Direction)>, bool) {
         }
     };
 
-    (all_pos, out_of_bounds)
+    Route { steps, cycle_start }
 }
 
-#[aoc(day6, part1)]
-pub fn part1(grid: &Grid<Tile>) -> usize {
-    let (path, out_of_bounds) = get_path(grid);
-    assert!(out_of_bounds);
-    path.iter().map(|(pos, _)| *pos).unique().count()
+/// Obstacle position that traps the guard together with the cycle it ends up in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct LoopWitness {
+    pub obstacle: Vec2i,
+    /// first step of the route that is part of the cycle
+    pub entry: (Vec2i, Direction),
+    pub cycle_length: usize,
 }
 
-#[aoc(day6, part2)]
-pub fn part2(grid: &Grid<Tile>) -> usize {
-    let (path, out_of_bounds) = get_path(grid);
-    assert!(out_of_bounds);
+/// Every empty cell on the route with the step right before the guard first enters it.
+///
+/// Placing an obstacle there leaves the route up to that step unchanged.
+fn loop_candidates(grid: &Grid<Tile>) -> Vec<(Vec2i, Vec2i, Direction)> {
+    let route = get_path(grid);
+    assert!(route.cycle_start.is_none());
 
-    let mut loops = 0;
-    let mut grid = grid.clone();
-    for obstacle_candidate in path.iter().map(|(pos, _)| *pos).unique() {
-        if grid[obstacle_candidate] == Tile::Empty {
-            grid[obstacle_candidate] = Tile::Obstacle;
+    let mut seen = FxHashSet::default();
+    route
+        .steps
+        .iter()
+        .tuple_windows()
+        .filter(|(_, (pos, _))| grid[*pos] == Tile::Empty && seen.insert(*pos))
+        .map(|((from, _), (pos, dir))| (*pos, *from, *dir))
+        .collect()
+}
+
+fn looping_obstacles(grid: &Grid<Tile>) -> impl ParallelIterator<Item = Vec2i> {
+    let table = JumpTable::new(grid);
+    loop_candidates(grid)
+        .into_par_iter()
+        .filter(move |&(obstacle, pos, dir)| table.is_loop(pos, dir, obstacle))
+        .map(|(obstacle, _, _)| obstacle)
+}
 
-            if let (_, false) = get_path(&grid) {
-                loops += 1;
+/// All obstacle positions that make the guard loop, ordered by the route.
+pub fn loop_witnesses(grid: &Grid<Tile>) -> Vec<LoopWitness> {
+    looping_obstacles(grid)
+        .map(|obstacle| {
+            let mut grid = grid.clone();
+            grid[obstacle] = Tile::Obstacle;
+            let route = get_path(&grid);
+            let cycle_start = route.cycle_start.unwrap();
+            LoopWitness {
+                obstacle,
+                entry: route.steps[cycle_start],
+                cycle_length: route.steps.len() - cycle_start,
             }
+        })
+        .collect()
+}
+
+/// Draws the route like the puzzle text, `|` and `-` for straight moves and `+` where the guard turns or crosses itself.
+///
+/// An additional obstacle is drawn as `O`.
+pub fn render(grid: &Grid<Tile>, route: &Route, obstacle: Option<Vec2i>) -> String {
+    let mut marks = Grid::new_from_element(grid.size_x, grid.size_y, (false, false));
+    let moves = route.steps.iter().map(|(_, dir)| *dir);
+    for ((pos, entered), left) in route
+        .steps
+        .iter()
+        .zip(moves.skip(1).chain(route.steps.last().map(|(_, dir)| *dir)))
+    {
+        for dir in [*entered, left] {
+            match dir {
+                Direction::North | Direction::South => marks[*pos].0 = true,
+                Direction::East | Direction::West => marks[*pos].1 = true,
+            }
+        }
+    }
 
-            grid[obstacle_candidate] = Tile::Empty;
+    let mut s = String::new();
+    for (pos, tile) in grid.pos_iter() {
+        s.push(match (tile, marks[pos]) {
+            _ if Some(pos) == obstacle => 'O',
+            (Tile::Obstacle, _) => '#',
+            (Tile::Guard(dir), _) => match dir {
+                Direction::North => '^',
+                Direction::South => 'v',
+                Direction::East => '>',
+                Direction::West => '<',
+            },
+            (Tile::Empty, (true, true)) => '+',
+            (Tile::Empty, (true, false)) => '|',
+            (Tile::Empty, (false, true)) => '-',
+            (Tile::Empty, (false, false)) => '.',
+        });
+        if pos.x as usize + 1 == grid.size_x {
+            s.push('\n');
         }
     }
+    s
+}
+
+#[aoc(day6, part1)]
+pub fn part1(grid: &Grid<Tile>) -> usize {
+    let route = get_path(grid);
+    assert!(route.cycle_start.is_none());
+    route.steps.iter().map(|(pos, _)| *pos).unique().count()
+}
 
-    loops
+#[aoc(day6, part2)]
+pub fn part2(grid: &Grid<Tile>) -> usize {
+    looping_obstacles(grid).count()
 }
 
 #[cfg(test)]
@@ -128,4 +296,104 @@ mod tests {
     fn test_part2() {
         assert_eq!(part2(&input_generator(INPUT)), 6);
     }
+
+    #[test]
+    fn test_render_loop() {
+        let grid = input_generator(INPUT);
+        let witnesses = loop_witnesses(&grid);
+        assert_eq!(witnesses.len(), 6);
+        assert_eq!(
+            witnesses[0],
+            LoopWitness {
+                obstacle: Vec2i::new(3, 6),
+                entry: (Vec2i::new(4, 5), Direction::North),
+                cycle_length: 18,
+            }
+        );
+
+        let mut blocked = grid.clone();
+        blocked[witnesses[0].obstacle] = Tile::Obstacle;
+        let route = get_path(&blocked);
+        // the start is entered from the east the second time
+        assert_eq!(route.cycle_start, Some(1));
+        assert_eq!(
+            render(&grid, &route, Some(witnesses[0].obstacle)),
+            r#"....#.....
+....+---+#
+....|...|.
+..#.|...|.
+....|..#|.
+....|...|.
+.#.O^---+.
+........#.
+#.........
+......#...
+"#
+        );
+    }
+
+    #[test]
+    fn test_render_route() {
+        let grid = input_generator(INPUT);
+        let route = get_path(&grid);
+        assert_eq!(route.steps[0], (Vec2i::new(4, 6), Direction::North));
+        assert_eq!(route.steps[5], (Vec2i::new(4, 1), Direction::North));
+        assert_eq!(route.steps[6], (Vec2i::new(5, 1), Direction::East));
+        assert_eq!(
+            render(&grid, &route, None),
+            r#"....#.....
+....+---+#
+....|...|.
+..#.|...|.
+..+-+-+#|.
+..|.|.|.|.
+.#+-^-+-+.
+.+----++#.
+#+----+|..
+......#|..
+"#
+        );
+    }
+
+    fn part2_naive(grid: &Grid<Tile>) -> usize {
+        let mut grid = grid.clone();
+        grid.pos_iter()
+            .filter(|(_, tile)| **tile == Tile::Empty)
+            .map(|(pos, _)| pos)
+            .collect_vec()
+            .into_iter()
+            .filter(|&pos| {
+                grid[pos] = Tile::Obstacle;
+                let route = get_path(&grid);
+                grid[pos] = Tile::Empty;
+                route.cycle_start.is_some()
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_part2_random_maps() {
+        let mut seed = 0x2024u64;
+        let mut random = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            seed >> 33
+        };
+        for _ in 0..10 {
+            let mut grid = Grid::new_from_element(40, 30, Tile::Empty);
+            for pos in grid.pos_iter().map(|(pos, _)| pos).collect_vec() {
+                if random() % 10 == 0 {
+                    grid[pos] = Tile::Obstacle;
+                }
+            }
+            let start = Vec2i::new(20, 15);
+            grid[start] = Tile::Guard(Direction::North);
+            if get_path(&grid).cycle_start.is_some() {
+                continue;
+            }
+
+            assert_eq!(part2(&grid), part2_naive(&grid));
+        }
+    }
 }
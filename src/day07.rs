@@ -1,9 +1,16 @@
 use crate::common::parse_split_whitespace;
 use aoc_runner_derive::{aoc, aoc_generator};
+use num::{BigUint, CheckedAdd, CheckedMul, CheckedSub, One, Zero};
+use std::fmt::{Debug, Display, Formatter};
+use std::iter;
 use std::num::NonZeroU64;
+use std::ops::{BitXor, Div, Rem};
+use std::str::FromStr;
 
-#[aoc_generator(day7)]
-pub fn input_generator(input: &str) -> Vec<(u64, Vec<NonZeroU64>)> {
+pub fn parse_equations<T: FromStr>(input: &str) -> Vec<(T, Vec<T>)>
+where
+    T::Err: Debug,
+{
     input
         .lines()
         .map(|l| {
@@ -13,52 +20,340 @@ pub fn input_generator(input: &str) -> Vec<(u64, Vec<NonZeroU64>)> {
         .collect()
 }
 
-const fn next_highest_power_of_10(n: NonZeroU64) -> NonZeroU64 {
+#[aoc_generator(day7)]
+pub fn input_generator(input: &str) -> Vec<(u64, Vec<u64>)> {
+    parse_equations(input)
+}
+
+const fn next_highest_power_of_10(n: NonZeroU64) -> Option<NonZeroU64> {
     const TEN: NonZeroU64 = NonZeroU64::new(10).unwrap();
-    TEN.checked_pow(n.ilog10() + 1).unwrap()
+    TEN.checked_pow(n.ilog10() + 1)
+}
+
+/// Values the equations can be solved over.
+pub trait Number:
+    Clone
+    + Ord
+    + Display
+    + Zero
+    + One
+    + CheckedAdd
+    + CheckedSub
+    + CheckedMul
+    + Div<Output = Self>
+    + Rem<Output = Self>
+    + BitXor<Output = Self>
+    + 'static
+{
+    /// The smallest power of `base` that is larger than `self`, `None` if it doesn't fit.
+    fn next_highest_power(&self, base: u32) -> Option<Self>;
+}
+
+impl Number for u64 {
+    fn next_highest_power(&self, base: u32) -> Option<Self> {
+        match NonZeroU64::new(*self) {
+            Some(n) if base == 10 => next_highest_power_of_10(n).map(NonZeroU64::get),
+            _ => (base as u64).checked_pow(self.checked_ilog(base as u64).unwrap_or(0) + 1),
+        }
+    }
+}
+
+impl Number for BigUint {
+    fn next_highest_power(&self, base: u32) -> Option<Self> {
+        Some(BigUint::from(base).pow(self.to_radix_le(base).len() as u32))
+    }
+}
+
+/// Outcome of undoing an operator from the right.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Inverse<T> {
+    /// the only left operand that gives the result
+    Lhs(T),
+    /// no left operand gives the result
+    Impossible,
+    /// the operator can't be undone, the left side has to be evaluated forwards instead
+    Unsupported,
+}
+
+impl<T> From<Option<T>> for Inverse<T> {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Inverse::Impossible, Inverse::Lhs)
+    }
+}
+
+/// A binary operator of a calibration equation, evaluated strictly left to right.
+///
+/// [`Display`] gives the symbol used when printing equations.
+pub trait Operator<T>: Display {
+    /// `lhs op rhs`, `None` if the result doesn't fit
+    fn apply(&self, lhs: &T, rhs: &T) -> Option<T>;
+
+    /// The `lhs` with `lhs op rhs == result`, used to search from the right.
+    fn invert(&self, _result: &T, _rhs: &T) -> Inverse<T> {
+        Inverse::Unsupported
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Add;
+
+impl<T: Number> Operator<T> for Add {
+    fn apply(&self, lhs: &T, rhs: &T) -> Option<T> {
+        lhs.checked_add(rhs)
+    }
+
+    fn invert(&self, result: &T, rhs: &T) -> Inverse<T> {
+        result.checked_sub(rhs).into()
+    }
+}
+
+impl Display for Add {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("+")
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Mul;
+
+impl<T: Number> Operator<T> for Mul {
+    fn apply(&self, lhs: &T, rhs: &T) -> Option<T> {
+        lhs.checked_mul(rhs)
+    }
+
+    fn invert(&self, result: &T, rhs: &T) -> Inverse<T> {
+        if rhs.is_zero() {
+            // every lhs works
+            return if result.is_zero() {
+                Inverse::Unsupported
+            } else {
+                Inverse::Impossible
+            };
+        }
+
+        (result.clone() % rhs.clone())
+            .is_zero()
+            .then(|| result.clone() / rhs.clone())
+            .into()
+    }
+}
+
+impl Display for Mul {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("*")
+    }
+}
+
+fn concat<T: Number>(lhs: &T, rhs: &T, base: u32) -> Option<T> {
+    // leading zeros vanish, even if rhs has as many digits as the type can hold
+    if lhs.is_zero() {
+        return Some(rhs.clone());
+    }
+
+    lhs.checked_mul(&rhs.next_highest_power(base)?)?
+        .checked_add(rhs)
+}
+
+fn strip_suffix<T: Number>(result: &T, rhs: &T, base: u32) -> Inverse<T> {
+    match rhs.next_highest_power(base) {
+        Some(pow) => (result.clone() % pow.clone() == *rhs)
+            .then(|| result.clone() / pow)
+            .into(),
+        // rhs has as many digits as the type can hold, so lhs has to be zero
+        None => (result == rhs).then(T::zero).into(),
+    }
+}
+
+/// Decimal concatenation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Concat;
+
+impl<T: Number> Operator<T> for Concat {
+    fn apply(&self, lhs: &T, rhs: &T) -> Option<T> {
+        concat(lhs, rhs, 10)
+    }
+
+    fn invert(&self, result: &T, rhs: &T) -> Inverse<T> {
+        strip_suffix(result, rhs, 10)
+    }
+}
+
+impl Display for Concat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("||")
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Sub;
+
+impl<T: Number> Operator<T> for Sub {
+    fn apply(&self, lhs: &T, rhs: &T) -> Option<T> {
+        lhs.checked_sub(rhs)
+    }
+
+    fn invert(&self, result: &T, rhs: &T) -> Inverse<T> {
+        result.checked_add(rhs).into()
+    }
+}
+
+impl Display for Sub {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("-")
+    }
+}
+
+/// Concatenation of the digits in the given base, printed as `||b` where `b` is the base.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ConcatBase {
+    base: u32,
+}
+
+impl ConcatBase {
+    pub const fn new(base: u32) -> Self {
+        assert!(base >= 2 && base <= 256);
+        Self { base }
+    }
+}
+
+impl<T: Number> Operator<T> for ConcatBase {
+    fn apply(&self, lhs: &T, rhs: &T) -> Option<T> {
+        concat(lhs, rhs, self.base)
+    }
+
+    fn invert(&self, result: &T, rhs: &T) -> Inverse<T> {
+        strip_suffix(result, rhs, self.base)
+    }
 }
 
-fn is_valid<const PART_2: bool>(result: u64, numbers: &[NonZeroU64]) -> bool {
+impl Display for ConcatBase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "||{}", self.base)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Xor;
+
+impl<T: Number> Operator<T> for Xor {
+    fn apply(&self, lhs: &T, rhs: &T) -> Option<T> {
+        Some(lhs.clone() ^ rhs.clone())
+    }
+
+    fn invert(&self, result: &T, rhs: &T) -> Inverse<T> {
+        Inverse::Lhs(result.clone() ^ rhs.clone())
+    }
+}
+
+impl Display for Xor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("^")
+    }
+}
+
+pub fn part1_operators<T: Number>() -> [&'static dyn Operator<T>; 2] {
+    [&Mul, &Add]
+}
+
+pub fn part2_operators<T: Number>() -> [&'static dyn Operator<T>; 3] {
+    [&Mul, &Concat, &Add]
+}
+
+type Witness<'a, T> = Vec<&'a dyn Operator<T>>;
+
+/// All ways to evaluate `numbers` from left to right together with the operators used.
+fn evaluations<'a, T: Number>(
+    ops: &'a [&'a dyn Operator<T>],
+    numbers: &'a [T],
+) -> Box<dyn Iterator<Item = (T, Witness<'a, T>)> + 'a> {
+    match numbers {
+        [] => Box::new(iter::empty()),
+        [n] => Box::new(iter::once((n.clone(), vec![]))),
+        [rest @ .., last] => Box::new(evaluations(ops, rest).flat_map(move |(value, witness)| {
+            ops.iter().filter_map(move |&op| {
+                let value = op.apply(&value, last)?;
+                let mut witness = witness.clone();
+                witness.push(op);
+                Some((value, witness))
+            })
+        })),
+    }
+}
+
+/// Lazily lists all operator sequences from `ops` that turn `numbers` into `result` when evaluated left to right.
+///
+/// The search goes from right to left and only falls back to evaluating the left side for operators without an inverse.
+pub fn solutions<'a, T: Number>(
+    ops: &'a [&'a dyn Operator<T>],
+    result: T,
+    numbers: &'a [T],
+) -> Box<dyn Iterator<Item = Witness<'a, T>> + 'a> {
     match numbers {
-        [] => result == 0 || result == 1,
-        [n] => n.get() == result,
-        [rest @ .., last] => {
-            if result < last.get() {
-                return false;
-            }
-
-            if result % *last == 0 && is_valid::<PART_2>(result / *last, rest) {
-                return true;
-            }
-
-            if PART_2 {
-                let pow10 = next_highest_power_of_10(*last);
-                if result % pow10 == last.get() && is_valid::<PART_2>(result / pow10, rest) {
-                    return true;
+        [] => Box::new(
+            (result.is_zero() || result.is_one())
+                .then(Vec::new)
+                .into_iter(),
+        ),
+        [n] => Box::new((*n == result).then(Vec::new).into_iter()),
+        [rest @ .., last] => Box::new(ops.iter().flat_map(move |&op| {
+            let lhs: Box<dyn Iterator<Item = Witness<'a, T>>> = match op.invert(&result, last) {
+                Inverse::Lhs(lhs) => solutions(ops, lhs, rest),
+                Inverse::Impossible => Box::new(iter::empty()),
+                Inverse::Unsupported => {
+                    let result = result.clone();
+                    Box::new(
+                        evaluations(ops, rest)
+                            .filter(move |(value, _)| {
+                                op.apply(value, last).is_some_and(|v| v == result)
+                            })
+                            .map(|(_, witness)| witness),
+                    )
                 }
-            }
+            };
+            lhs.map(move |mut witness| {
+                witness.push(op);
+                witness
+            })
+        })),
+    }
+}
+
+pub fn solve<'a, T: Number>(
+    ops: &'a [&'a dyn Operator<T>],
+    result: T,
+    numbers: &'a [T],
+) -> Option<Witness<'a, T>> {
+    solutions(ops, result, numbers).next()
+}
 
-            is_valid::<PART_2>(result - last.get(), rest)
+/// Formats an equation like `190 = 10 * 19`.
+pub fn format_equation<T: Display>(result: &T, numbers: &[T], ops: &[&dyn Operator<T>]) -> String {
+    assert_eq!(ops.len() + 1, numbers.len().max(1));
+    let mut s = format!("{result} =");
+    for (i, n) in numbers.iter().enumerate() {
+        if i > 0 {
+            s.push_str(&format!(" {}", ops[i - 1]));
         }
+        s.push_str(&format!(" {n}"));
     }
+    s
 }
 
-#[aoc(day7, part1)]
-pub fn part1(input: &[(u64, Vec<NonZeroU64>)]) -> u64 {
+pub fn total_calibration_result<T: Number>(ops: &[&dyn Operator<T>], input: &[(T, Vec<T>)]) -> T {
     input
         .iter()
-        .filter(|(result, numbers)| is_valid::<false>(*result, numbers))
-        .map(|(result, _)| *result)
-        .sum()
+        .filter(|(result, numbers)| solve(ops, result.clone(), numbers).is_some())
+        .fold(T::zero(), |acc, (result, _)| acc + result.clone())
+}
+
+#[aoc(day7, part1)]
+pub fn part1(input: &[(u64, Vec<u64>)]) -> u64 {
+    total_calibration_result(&part1_operators(), input)
 }
 
 #[aoc(day7, part2)]
-pub fn part2(input: &[(u64, Vec<NonZeroU64>)]) -> u64 {
-    input
-        .iter()
-        .filter(|(result, numbers)| is_valid::<true>(*result, numbers))
-        .map(|(result, _)| *result)
-        .sum()
+pub fn part2(input: &[(u64, Vec<u64>)]) -> u64 {
+    total_calibration_result(&part2_operators(), input)
 }
 
 #[cfg(test)]
@@ -86,55 +381,203 @@ mod tests {
         assert_eq!(part2(&input_generator(INPUT)), 11387);
     }
 
+    #[test]
+    fn test_witnesses() {
+        let input = input_generator(INPUT);
+        let audit =
+            |i: usize, ops: &[&dyn Operator<u64>]| format_equation(&input[i].0, &input[i].1, ops);
+        let part1_operators = part1_operators();
+        let part2_operators = part2_operators();
+
+        let ops = solve(&part1_operators, input[0].0, &input[0].1).unwrap();
+        assert_eq!(audit(0, &ops), "190 = 10 * 19");
+        assert_eq!(
+            solutions(&part1_operators, input[1].0, &input[1].1)
+                .map(|ops| audit(1, &ops))
+                .collect::<Vec<_>>(),
+            ["3267 = 81 + 40 * 27", "3267 = 81 * 40 + 27"]
+        );
+        assert!(solve(&part1_operators, input[4].0, &input[4].1).is_none());
+
+        let ops = solve(&part2_operators, input[4].0, &input[4].1).unwrap();
+        assert_eq!(audit(4, &ops), "7290 = 6 * 8 || 6 * 15");
+        let ops = solve(&part2_operators, input[3].0, &input[3].1).unwrap();
+        assert_eq!(audit(3, &ops), "156 = 15 || 6");
+        assert!(solve(&part2_operators, input[2].0, &input[2].1).is_none());
+    }
+
+    fn audit_all<T: Number + FromStr>(ops: &[&dyn Operator<T>], input: &str) -> Vec<String>
+    where
+        T::Err: Debug,
+    {
+        parse_equations::<T>(input)
+            .iter()
+            .map(
+                |(result, numbers)| match solve(ops, result.clone(), numbers) {
+                    Some(witness) => format_equation(result, numbers, &witness),
+                    None => format!("{result}: invalid"),
+                },
+            )
+            .collect()
+    }
+
+    #[test]
+    fn test_extra_operators() {
+        const BINARY: ConcatBase = ConcatBase::new(2);
+        assert_eq!(
+            audit_all::<u64>(
+                &[&Sub, &BINARY, &Xor],
+                "5: 10 3 2\n7: 1 3\n6: 5 3\n13: 3 5 1\n9: 2 2"
+            ),
+            [
+                "5 = 10 - 3 - 2",
+                "7 = 1 ||2 3",
+                "6 = 5 ^ 3",
+                "13 = 3 ^ 5 ||2 1",
+                "9: invalid"
+            ]
+        );
+        assert_eq!(BINARY.apply(&5u64, &6), Some(46));
+        assert_eq!(BINARY.invert(&46u64, &6), Inverse::Lhs(5));
+        assert_eq!(Sub.invert(&u64::MAX, &1), Inverse::Impossible);
+    }
+
+    struct Max;
+
+    impl Operator<u64> for Max {
+        fn apply(&self, lhs: &u64, rhs: &u64) -> Option<u64> {
+            Some(*lhs.max(rhs))
+        }
+    }
+
+    impl Display for Max {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            f.write_str("max")
+        }
+    }
+
+    #[test]
+    fn test_operator_without_inverse() {
+        assert_eq!(
+            audit_all::<u64>(&[&Add, &Max], "9: 9 3 1\n10: 9 3 1\n13: 9 3 1\n2: 9 3 1"),
+            [
+                "9 = 9 max 3 max 1",
+                "10 = 9 max 3 + 1",
+                "13 = 9 + 3 + 1",
+                "2: invalid"
+            ]
+        );
+    }
+
     #[test]
     fn test_power10() {
         assert_eq!(
             next_highest_power_of_10(NonZeroU64::new(1).unwrap()),
-            NonZeroU64::new(10).unwrap()
+            NonZeroU64::new(10)
         );
         assert_eq!(
             next_highest_power_of_10(NonZeroU64::new(2).unwrap()),
-            NonZeroU64::new(10).unwrap()
+            NonZeroU64::new(10)
         );
         assert_eq!(
             next_highest_power_of_10(NonZeroU64::new(3).unwrap()),
-            NonZeroU64::new(10).unwrap()
+            NonZeroU64::new(10)
         );
         assert_eq!(
             next_highest_power_of_10(NonZeroU64::new(4).unwrap()),
-            NonZeroU64::new(10).unwrap()
+            NonZeroU64::new(10)
         );
         assert_eq!(
             next_highest_power_of_10(NonZeroU64::new(5).unwrap()),
-            NonZeroU64::new(10).unwrap()
+            NonZeroU64::new(10)
         );
         assert_eq!(
             next_highest_power_of_10(NonZeroU64::new(6).unwrap()),
-            NonZeroU64::new(10).unwrap()
+            NonZeroU64::new(10)
         );
         assert_eq!(
             next_highest_power_of_10(NonZeroU64::new(7).unwrap()),
-            NonZeroU64::new(10).unwrap()
+            NonZeroU64::new(10)
         );
         assert_eq!(
             next_highest_power_of_10(NonZeroU64::new(8).unwrap()),
-            NonZeroU64::new(10).unwrap()
+            NonZeroU64::new(10)
         );
         assert_eq!(
             next_highest_power_of_10(NonZeroU64::new(9).unwrap()),
-            NonZeroU64::new(10).unwrap()
+            NonZeroU64::new(10)
         );
         assert_eq!(
             next_highest_power_of_10(NonZeroU64::new(10).unwrap()),
-            NonZeroU64::new(100).unwrap()
+            NonZeroU64::new(100)
         );
         assert_eq!(
             next_highest_power_of_10(NonZeroU64::new(99).unwrap()),
-            NonZeroU64::new(100).unwrap()
+            NonZeroU64::new(100)
         );
         assert_eq!(
             next_highest_power_of_10(NonZeroU64::new(100).unwrap()),
-            NonZeroU64::new(1000).unwrap()
+            NonZeroU64::new(1000)
+        );
+        assert_eq!(
+            next_highest_power_of_10(NonZeroU64::new(10_000_000_000_000_000_000).unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_big_numbers() {
+        let input = "123456789012345678901234567890: 1234567890 1234567890 1234567890
+1234567890123456789012345678901234567890: 1 234567890123456789012345678901234567890 0
+0: 5 0 7
+50: 5 0
+5: 5 0
+1: 0 0";
+        assert_eq!(
+            audit_all::<BigUint>(&part2_operators(), input),
+            [
+                "123456789012345678901234567890 = 1234567890 || 1234567890 || 1234567890",
+                "1234567890123456789012345678901234567890 = 1 || 234567890123456789012345678901234567890 + 0",
+                "0 = 5 * 0 * 7",
+                "50 = 5 || 0",
+                "5 = 5 + 0",
+                "1: invalid",
+            ]
+        );
+        assert_eq!(
+            total_calibration_result(&part2_operators(), &parse_equations::<BigUint>(input)),
+            "1234567890246913578024691357802469135835".parse().unwrap()
+        );
+        assert_eq!(
+            audit_all::<BigUint>(
+                &[&Concat, &Xor, &ConcatBase::new(16)],
+                "4660: 1 2 3 4\n0: 0 0 0"
+            ),
+            ["4660 = 1 ||16 2 ||16 3 ||16 4", "0 = 0 || 0 || 0"]
+        );
+    }
+
+    #[test]
+    fn test_u64_overflow() {
+        let input = "5: 1 10000000000000000000\n18446744073709551615: 18446744073709551615 1";
+        assert_eq!(
+            audit_all::<u64>(&part2_operators(), input),
+            [
+                "5: invalid",
+                "18446744073709551615 = 18446744073709551615 * 1"
+            ]
+        );
+
+        let full = 10_000_000_000_000_000_000u64;
+        assert_eq!(Concat.apply(&0, &full), Some(full));
+        assert_eq!(Concat.invert(&full, &full), Inverse::Lhs(0));
+        assert_eq!(Concat.apply(&1, &full), None);
+        assert_eq!(
+            audit_all::<u64>(
+                &part2_operators(),
+                "10000000000000000000: 0 10000000000000000000"
+            ),
+            ["10000000000000000000 = 0 || 10000000000000000000"]
         );
     }
 }
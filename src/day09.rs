@@ -1,4 +1,7 @@
 use aoc_runner_derive::{aoc, aoc_generator};
+use itertools::Itertools;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Disk {
@@ -6,6 +9,14 @@ pub enum Disk {
     Free { length: u32 },
 }
 
+impl Disk {
+    pub fn length(&self) -> u32 {
+        match self {
+            Disk::File { length, .. } | Disk::Free { length } => *length,
+        }
+    }
+}
+
 #[aoc_generator(day9)]
 pub fn input_generator(input: &str) -> Vec<Disk> {
     input
@@ -26,109 +37,416 @@ pub fn input_generator(input: &str) -> Vec<Disk> {
         .collect()
 }
 
-fn compact_disk<const PART2: bool>(disk_map: &[Disk]) -> Vec<Disk> {
-    let mut compact = disk_map.to_vec();
-    if compact.len() <= 1 {
-        return compact;
+/// A single compaction step: `length` blocks of file `id` move from block `from` to block `to`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Move {
+    pub id: u32,
+    pub from: u64,
+    pub to: u64,
+    pub length: u32,
+}
+
+/// Strategy for moving file blocks towards the start of the disk.
+pub trait CompactionPolicy {
+    /// Returns the compacted disk map and reports every move in order.
+    fn compact(&self, disk_map: &[Disk], on_move: &mut dyn FnMut(Move)) -> Vec<Disk>;
+}
+
+/// Moves single blocks from the end of the disk into the leftmost free block.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BlockWise;
+
+/// Moves whole files, from the end of the disk, into the leftmost free span that is large enough.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct WholeFile;
+
+/// Moves whole files, from the end of the disk, into the smallest free span that is large enough.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BestFit;
+
+/// Moves whole files, from the end of the disk, into the largest free span.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct WorstFit;
+
+/// Visits free spans from the start of the disk, including space freed by earlier moves, and
+/// fills each with the leftmost files behind it that fit.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MoveLeftmostFitting;
+
+impl CompactionPolicy for BlockWise {
+    fn compact(&self, disk_map: &[Disk], on_move: &mut dyn FnMut(Move)) -> Vec<Disk> {
+        compact_blocks(disk_map, on_move)
     }
+}
 
-    let mut i = compact.len() - 1;
-    while i > 0 {
-        let Disk::File {
-            id,
-            length: mut file_length,
-        } = compact[i]
+impl CompactionPolicy for WholeFile {
+    fn compact(&self, disk_map: &[Disk], on_move: &mut dyn FnMut(Move)) -> Vec<Disk> {
+        compact_files(disk_map, on_move, |spans| {
+            spans.min_by_key(|&(_, span_start)| span_start)
+        })
+    }
+}
+
+impl CompactionPolicy for BestFit {
+    fn compact(&self, disk_map: &[Disk], on_move: &mut dyn FnMut(Move)) -> Vec<Disk> {
+        compact_files(disk_map, on_move, |spans| spans.next())
+    }
+}
+
+impl CompactionPolicy for WorstFit {
+    fn compact(&self, disk_map: &[Disk], on_move: &mut dyn FnMut(Move)) -> Vec<Disk> {
+        compact_files(disk_map, on_move, |spans| spans.last())
+    }
+}
+
+impl CompactionPolicy for MoveLeftmostFitting {
+    fn compact(&self, disk_map: &[Disk], on_move: &mut dyn FnMut(Move)) -> Vec<Disk> {
+        fill_gaps(disk_map, on_move)
+    }
+}
+
+pub fn compact_disk(disk_map: &[Disk], policy: &impl CompactionPolicy) -> Vec<Disk> {
+    policy.compact(disk_map, &mut |_| {})
+}
+
+/// Block-level view of a disk map, yielding the file id stored in each block.
+pub fn iter_blocks(disk_map: &[Disk]) -> impl Iterator<Item = Option<u32>> + '_ {
+    disk_map.iter().flat_map(|d| {
+        let id = match d {
+            Disk::File { id, .. } => Some(*id),
+            Disk::Free { .. } => None,
+        };
+        (0..d.length()).map(move |_| id)
+    })
+}
+
+fn render_blocks(blocks: impl IntoIterator<Item = Option<u32>>, multi_digit: bool) -> String {
+    blocks
+        .into_iter()
+        .map(|id| match id {
+            None => ".".to_string(),
+            Some(id) if multi_digit => format!("[{id}]"),
+            Some(id) => id.to_string(),
+        })
+        .collect()
+}
+
+/// Renders a disk map in the puzzle's notation like `00...111...2`.
+///
+/// If any file id has more than one digit, every file block is written as `[id]` instead.
+pub fn render(disk_map: &[Disk]) -> String {
+    render_blocks(iter_blocks(disk_map), has_multi_digit_ids(disk_map))
+}
+
+fn has_multi_digit_ids(disk_map: &[Disk]) -> bool {
+    disk_map
+        .iter()
+        .any(|d| matches!(d, Disk::File { id, .. } if *id >= 10))
+}
+
+/// Renders the disk map before compaction and after every move.
+pub fn compaction_trace(disk_map: &[Disk], policy: &impl CompactionPolicy) -> Vec<String> {
+    let multi_digit = has_multi_digit_ids(disk_map);
+    let mut blocks = iter_blocks(disk_map).collect::<Vec<_>>();
+    let mut frames = vec![render_blocks(blocks.iter().copied(), multi_digit)];
+    policy.compact(disk_map, &mut |m| {
+        for i in 0..m.length as usize {
+            let (from, to) = (m.from as usize + i, m.to as usize + i);
+            assert_eq!(blocks[from], Some(m.id));
+            assert_eq!(blocks[to], None);
+            blocks[to] = blocks[from].take();
+        }
+        frames.push(render_blocks(blocks.iter().copied(), multi_digit));
+    });
+    frames
+}
+
+fn disk_length(disk_map: &[Disk]) -> u64 {
+    disk_map.iter().map(|d| d.length() as u64).sum()
+}
+
+fn compact_blocks(disk_map: &[Disk], on_move: &mut dyn FnMut(Move)) -> Vec<Disk> {
+    let total_length = disk_length(disk_map);
+    let starts = disk_map
+        .iter()
+        .scan(0, |start, d| {
+            let current = *start;
+            *start += d.length() as u64;
+            Some(current)
+        })
+        .collect::<Vec<_>>();
+    let mut disk_map = disk_map.to_vec();
+    let mut end = 0;
+    let mut compact = vec![];
+    let mut i = 0;
+    // everything from j on has been moved already
+    let mut j = disk_map.len();
+    while i < j {
+        let Disk::Free {
+            length: free_length @ 1..,
+        } = disk_map[i]
         else {
-            i -= 1;
+            compact.push(disk_map[i]);
+            end += disk_map[i].length() as u64;
+            i += 1;
             continue;
         };
-        if file_length == 0 {
-            i -= 1;
-            continue;
+
+        while j > i + 1 && !matches!(disk_map[j - 1], Disk::File { length, .. } if length > 0) {
+            j -= 1;
         }
+        let Some(Disk::File { id, length }) = disk_map.get_mut(j - 1).filter(|_| j > i + 1) else {
+            break;
+        };
 
-        let mut j = 0;
-        while j < i {
-            let Disk::Free {
-                length: free_length,
-            } = &mut compact[j]
-            else {
-                j += 1;
-                continue;
-            };
-            if (PART2 && *free_length < file_length) || *free_length == 0 {
-                j += 1;
-                continue;
-            }
+        let blocks = free_length.min(*length);
+        *length -= blocks;
+        on_move(Move {
+            id: *id,
+            from: starts[j - 1] + *length as u64,
+            to: end,
+            length: blocks,
+        });
+        compact.push(Disk::File {
+            id: *id,
+            length: blocks,
+        });
+        end += blocks as u64;
+        disk_map[i] = Disk::Free {
+            length: free_length - blocks,
+        };
+        if blocks == free_length {
+            i += 1;
+        }
+    }
 
-            let blocks = file_length.min(*free_length);
-            assert!(blocks > 0);
-            file_length -= blocks;
-            *free_length -= blocks;
+    let free_length = total_length - disk_length(&compact);
+    if free_length > 0 {
+        compact.push(Disk::Free {
+            length: free_length as u32,
+        });
+    }
+    compact
+}
 
-            let new_block = Disk::File { id, length: blocks };
-            if *free_length == 0 {
-                compact[j] = new_block;
-            } else {
-                compact.insert(j, new_block);
-                j += 1;
-                i += 1;
+/// Moves whole files from the end of the disk into the free span picked by `select`.
+///
+/// Free spans are kept in one min-heap of start positions per span length, so `select` only gets
+/// the leftmost span of each length that fits and lies before the file, in increasing length.
+fn compact_files(
+    disk_map: &[Disk],
+    on_move: &mut dyn FnMut(Move),
+    select: impl Fn(&mut dyn Iterator<Item = (usize, u64)>) -> Option<(usize, u64)>,
+) -> Vec<Disk> {
+    let mut files = vec![];
+    let mut free_spans: Vec<BinaryHeap<Reverse<u64>>> = vec![];
+    let mut start = 0;
+    for d in disk_map {
+        let length = d.length() as usize;
+        match d {
+            Disk::File { id, .. } => files.push((start, *id, length)),
+            Disk::Free { .. } if length > 0 => {
+                if free_spans.len() <= length {
+                    free_spans.resize_with(length + 1, Default::default);
+                }
+                free_spans[length].push(Reverse(start));
             }
+            Disk::Free { .. } => {}
+        }
+        start += length as u64;
+    }
 
-            if file_length == 0 {
-                compact[i] = Disk::Free { length: blocks };
-                break;
-            } else if let Some(Disk::Free { length }) = compact.get_mut(i + 1) {
-                *length += blocks;
-            } else {
-                compact.insert(i + 1, Disk::Free { length: blocks });
+    // freed space is never reused because files only move left and are processed right to left
+    for (start, id, length) in files.iter_mut().rev() {
+        if *length == 0 {
+            continue;
+        }
+
+        let Some((span_length, span_start)) = select(
+            &mut free_spans
+                .iter()
+                .enumerate()
+                .skip(*length)
+                .filter_map(|(span_length, spans)| spans.peek().map(|s| (span_length, s.0)))
+                .filter(|&(_, span_start)| span_start < *start),
+        ) else {
+            continue;
+        };
+
+        free_spans[span_length].pop();
+        if span_length > *length {
+            free_spans[span_length - *length].push(Reverse(span_start + *length as u64));
+        }
+        on_move(Move {
+            id: *id,
+            from: *start,
+            to: span_start,
+            length: *length as u32,
+        });
+        *start = span_start;
+    }
+
+    layout(files, disk_length(disk_map))
+}
+
+fn fill_gaps(disk_map: &[Disk], on_move: &mut dyn FnMut(Move)) -> Vec<Disk> {
+    let mut files = vec![];
+    let mut files_by_length: Vec<BinaryHeap<Reverse<(u64, usize)>>> = vec![];
+    let mut free_spans = BTreeMap::new();
+    let mut start = 0;
+    for d in disk_map {
+        let length = d.length() as usize;
+        match d {
+            Disk::File { id, .. } if length > 0 => {
+                if files_by_length.len() <= length {
+                    files_by_length.resize_with(length + 1, Default::default);
+                }
+                files_by_length[length].push(Reverse((start, files.len())));
+                files.push((start, *id, length));
+            }
+            Disk::File { id, .. } => files.push((start, *id, length)),
+            Disk::Free { .. } if length > 0 => {
+                free_spans.insert(start, length as u64);
             }
+            Disk::Free { .. } => {}
+        }
+        start += length as u64;
+    }
 
-            j += 1;
+    let mut cursor = 0;
+    while let Some((span_start, span_length)) = free_spans.range(cursor..).next() {
+        let (span_start, span_length) = (*span_start, *span_length);
+        // files before this span can never move again
+        for files in files_by_length.iter_mut() {
+            while files.peek().is_some_and(|f| f.0 .0 < span_start) {
+                files.pop();
+            }
         }
 
-        if file_length > 0 {
-            compact[i] = Disk::File {
-                id,
-                length: file_length,
-            };
+        let Some(length) = (1..files_by_length.len().min(span_length as usize + 1))
+            .filter(|&length| !files_by_length[length].is_empty())
+            .min_by_key(|&length| files_by_length[length].peek().unwrap().0 .0)
+        else {
+            cursor = span_start + 1;
+            continue;
+        };
+
+        let Reverse((file_start, i)) = files_by_length[length].pop().unwrap();
+        let length = length as u64;
+        on_move(Move {
+            id: files[i].1,
+            from: file_start,
+            to: span_start,
+            length: length as u32,
+        });
+        files[i].0 = span_start;
+
+        free_spans.remove(&span_start);
+        if span_length > length {
+            free_spans.insert(span_start + length, span_length - length);
         }
 
-        i -= 1;
+        // free the old location, merging it with adjacent free spans
+        let (mut freed_start, mut freed_length) = (file_start, length);
+        if let Some((&before, &before_length)) = free_spans.range(..freed_start).next_back() {
+            if before + before_length == freed_start {
+                free_spans.remove(&before);
+                freed_start = before;
+                freed_length += before_length;
+            }
+        }
+        if let Some(after_length) = free_spans.remove(&(freed_start + freed_length)) {
+            freed_length += after_length;
+        }
+        free_spans.insert(freed_start, freed_length);
+        cursor = span_start;
     }
 
+    layout(files, disk_length(disk_map))
+}
+
+/// Builds a disk map from files given as `(start, id, length)`.
+fn layout(mut files: Vec<(u64, u32, usize)>, total_length: u64) -> Vec<Disk> {
+    files.sort_unstable_by_key(|&(start, _, _)| start);
+    let mut compact = vec![];
+    let mut end = 0;
+    for (start, id, length) in files {
+        if start > end {
+            compact.push(Disk::Free {
+                length: (start - end) as u32,
+            });
+        }
+        compact.push(Disk::File {
+            id,
+            length: length as u32,
+        });
+        end = end.max(start + length as u64);
+    }
+    if total_length > end {
+        compact.push(Disk::Free {
+            length: (total_length - end) as u32,
+        });
+    }
     compact
 }
 
-fn checksum(disk_map: &[Disk]) -> u64 {
-    disk_map
-        .iter()
-        .flat_map(|d| {
-            let (length, id) = match d {
-                Disk::File { id, length } => (*length as u64, *id as u64),
-                Disk::Free { length } => (*length as u64, 0),
-            };
-            (0..length).map(move |_| id)
-        })
+pub fn checksum(disk_map: &[Disk]) -> u64 {
+    iter_blocks(disk_map)
         .enumerate()
-        .map(|(i, id)| i as u64 * id)
+        .map(|(i, id)| i as u64 * id.unwrap_or(0) as u64)
         .sum()
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct DiskReport {
+    pub checksum: u64,
+    /// maximal runs of free blocks
+    pub free_runs: usize,
+    pub largest_free_run: u64,
+    /// maximal runs of blocks belonging to the same file
+    pub file_fragments: usize,
+}
+
+pub fn report(disk_map: &[Disk]) -> DiskReport {
+    let mut report = DiskReport {
+        checksum: checksum(disk_map),
+        ..Default::default()
+    };
+    for (id, run) in &disk_map
+        .iter()
+        .filter(|d| d.length() > 0)
+        .chunk_by(|d| match d {
+            Disk::File { id, .. } => Some(*id),
+            Disk::Free { .. } => None,
+        })
+    {
+        if id.is_some() {
+            report.file_fragments += 1;
+        } else {
+            report.free_runs += 1;
+            let length = run.map(|d| d.length() as u64).sum();
+            report.largest_free_run = report.largest_free_run.max(length);
+        }
+    }
+
+    report
+}
+
 #[aoc(day9, part1)]
 pub fn part1(input: &[Disk]) -> u64 {
-    checksum(&compact_disk::<false>(input))
+    checksum(&compact_disk(input, &BlockWise))
 }
 
 #[aoc(day9, part2)]
 pub fn part2(input: &[Disk]) -> u64 {
-    checksum(&compact_disk::<true>(input))
+    checksum(&compact_disk(input, &WholeFile))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
+    use std::time::{Duration, Instant};
 
     const INPUT: &str = r#"2333133121414131402"#;
     const CHALLENGE: &str = include_str!("../alternative_inputs/day9.txt");
@@ -150,6 +468,130 @@ mod tests {
 
     #[test]
     fn test_part2_challenge() {
-        assert_eq!(part2(&input_generator(CHALLENGE)), 97898222299196);
+        let input = input_generator(CHALLENGE);
+        let start = Instant::now();
+        assert_eq!(part2(&input), 97898222299196);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_render() {
+        let input = input_generator(INPUT);
+        assert_eq!(render(&input), "00...111...2...333.44.5555.6666.777.888899");
+        assert_eq!(
+            render(&compact_disk(&input, &BlockWise)),
+            "0099811188827773336446555566.............."
+        );
+        assert_eq!(
+            render(&compact_disk(&input, &WholeFile)),
+            "00992111777.44.333....5555.6666.....8888.."
+        );
+        assert_eq!(
+            render(&input_generator("10101010101010101010211")),
+            "[0][1][2][3][4][5][6][7][8][9][10][10].[11]"
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let input = input_generator(INPUT);
+        assert_eq!(
+            compaction_trace(&input, &WholeFile),
+            [
+                "00...111...2...333.44.5555.6666.777.888899",
+                "0099.111...2...333.44.5555.6666.777.8888..",
+                "0099.1117772...333.44.5555.6666.....8888..",
+                "0099.111777244.333....5555.6666.....8888..",
+                "00992111777.44.333....5555.6666.....8888..",
+            ]
+        );
+
+        let trace = compaction_trace(&input, &BlockWise);
+        assert_eq!(trace[1], "0099.111...2...333.44.5555.6666.777.8888..");
+        assert_eq!(
+            trace.last().unwrap(),
+            "0099811188827773336446555566.............."
+        );
+    }
+
+    #[test]
+    fn test_policies() {
+        let input = input_generator("1412102");
+        assert_eq!(render(&compact_disk(&input, &WholeFile)), "03321......");
+        assert_eq!(render(&compact_disk(&input, &BestFit)), "021...33...");
+        assert_eq!(render(&compact_disk(&input, &WorstFit)), "03321......");
+        assert_eq!(
+            render(&compact_disk(&input, &MoveLeftmostFitting)),
+            "01233......"
+        );
+
+        let input = input_generator("1214102");
+        assert_eq!(render(&compact_disk(&input, &WholeFile)), "03312......");
+        assert_eq!(render(&compact_disk(&input, &WorstFit)), "021.33.....");
+
+        let input = input_generator(INPUT);
+        assert_eq!(
+            compaction_trace(&input, &MoveLeftmostFitting)
+                .last()
+                .unwrap(),
+            &render(&compact_disk(&input, &MoveLeftmostFitting))
+        );
+        for frames in [
+            compaction_trace(&input, &BestFit),
+            compaction_trace(&input, &WorstFit),
+            compaction_trace(&input, &MoveLeftmostFitting),
+        ] {
+            assert_eq!(
+                frames.last().unwrap().chars().sorted().collect::<String>(),
+                frames[0].chars().sorted().collect::<String>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_zero_length_file() {
+        let input = input_generator("8647520");
+        let compact = compact_disk(&input, &WholeFile);
+        assert_eq!(render(&compact), "0000000022222.1111..............");
+        assert_eq!(checksum(&compact), 162);
+        for compact in [
+            compact_disk(&input, &BestFit),
+            compact_disk(&input, &WorstFit),
+            compact_disk(&input, &MoveLeftmostFitting),
+        ] {
+            assert_eq!(disk_length(&compact), 32);
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let input = input_generator(INPUT);
+        assert_eq!(
+            report(&input),
+            DiskReport {
+                checksum: checksum(&input),
+                free_runs: 8,
+                largest_free_run: 3,
+                file_fragments: 10,
+            }
+        );
+        assert_eq!(
+            report(&compact_disk(&input, &BlockWise)),
+            DiskReport {
+                checksum: 1928,
+                free_runs: 1,
+                largest_free_run: 14,
+                file_fragments: 13,
+            }
+        );
+        assert_eq!(
+            report(&compact_disk(&input, &WholeFile)),
+            DiskReport {
+                checksum: 2858,
+                free_runs: 6,
+                largest_free_run: 5,
+                file_fragments: 10,
+            }
+        );
     }
 }
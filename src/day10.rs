@@ -1,7 +1,10 @@
 use crate::common::{Grid, Vec2i};
 use aoc_runner_derive::{aoc, aoc_generator};
-use num::ToPrimitive;
+use itertools::Itertools;
+use num::{One, ToPrimitive, Zero};
 use pathfinding::prelude::*;
+use std::iter;
+use std::ops::Add;
 use tinyvec::ArrayVec;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -30,45 +33,186 @@ pub fn input_generator(input: &str) -> (Grid<Elevation>, Vec<Vec2i>) {
     (grid, trailheads)
 }
 
-fn neighbors(grid: &Grid<Elevation>, pos: Vec2i) -> impl IntoIterator<Item = Vec2i> {
-    let next = grid[pos].0 + 1;
-    [
+/// Which steps a hiker may take between neighboring cells.
+///
+/// The default is the puzzle rule: exactly one up in one of the four cardinal directions.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct StepRule {
+    /// largest height difference of a single step
+    pub max_climb: u8,
+    /// whether steps may also go down, by at most `max_climb`
+    pub allow_descent: bool,
+    /// whether the four diagonal neighbors can be reached as well
+    pub diagonal: bool,
+}
+
+impl Default for StepRule {
+    fn default() -> Self {
+        Self {
+            max_climb: 1,
+            allow_descent: false,
+            diagonal: false,
+        }
+    }
+}
+
+impl StepRule {
+    pub fn allows(&self, from: Elevation, to: Elevation) -> bool {
+        let diff = to.0 as i16 - from.0 as i16;
+        let max = self.max_climb as i16;
+        (1..=max).contains(&diff) || (self.allow_descent && (-max..=-1).contains(&diff))
+    }
+
+    /// Whether every trail strictly climbs, so elevation levels can be processed in order.
+    fn is_monotonic(&self) -> bool {
+        !self.allow_descent
+    }
+}
+
+fn neighbors(grid: &Grid<Elevation>, pos: Vec2i, rule: &StepRule) -> ArrayVec<[Vec2i; 8]> {
+    const STRAIGHT: [Vec2i; 4] = [
         Vec2i::new(1, 0),
         Vec2i::new(-1, 0),
         Vec2i::new(0, 1),
         Vec2i::new(0, -1),
-    ]
-    .into_iter()
-    .map(|dir| pos + dir)
-    .filter(|pos| grid.in_bounds(pos) && grid[*pos].0 == next)
-    .collect::<ArrayVec<[Vec2i; 4]>>()
+    ];
+    const DIAGONAL: [Vec2i; 4] = [
+        Vec2i::new(1, 1),
+        Vec2i::new(-1, 1),
+        Vec2i::new(1, -1),
+        Vec2i::new(-1, -1),
+    ];
+
+    let diagonal: &[Vec2i] = if rule.diagonal { &DIAGONAL } else { &[] };
+    STRAIGHT
+        .iter()
+        .chain(diagonal)
+        .map(|dir| pos + dir)
+        .filter(|next| grid.in_bounds(next) && rule.allows(grid[pos], grid[*next]))
+        .collect()
+}
+
+/// Lazily lists all trails from `trailhead` to a cell of height 9, without visiting any cell twice.
+pub fn trails<'a>(
+    grid: &'a Grid<Elevation>,
+    trailhead: Vec2i,
+    rule: &'a StepRule,
+) -> impl Iterator<Item = Vec<Vec2i>> + 'a {
+    let mut trail = vec![];
+    let mut stack = vec![(trailhead, 0)];
+    iter::from_fn(move || {
+        while let Some((pos, depth)) = stack.pop() {
+            trail.truncate(depth);
+            trail.push(pos);
+            if grid[pos].0 == 9 {
+                return Some(trail.clone());
+            }
+
+            for next in neighbors(grid, pos, rule) {
+                if !trail.contains(&next) {
+                    stack.push((next, depth + 1));
+                }
+            }
+        }
+
+        None
+    })
+}
+
+/// Number of distinct height 9 cells reachable from each trailhead.
+///
+/// For rules that only climb this is one pass over the elevation levels from 9 down to 0,
+/// where every cell collects the set of reachable 9s from its neighbors.
+pub fn scores(grid: &Grid<Elevation>, trailheads: &[Vec2i], rule: &StepRule) -> Vec<usize> {
+    if !rule.is_monotonic() {
+        return trailheads
+            .iter()
+            .map(|t| {
+                bfs_reach(*t, |&pos| neighbors(grid, pos, rule))
+                    .filter(|&p| grid[p].0 == 9)
+                    .count()
+            })
+            .collect();
+    }
+
+    let peaks = grid
+        .pos_iter()
+        .filter(|(_, e)| e.0 == 9)
+        .map(|(p, _)| p)
+        .collect_vec();
+    let words = peaks.len().div_ceil(64);
+    let mut reachable = Grid::new_from_element(grid.size_x, grid.size_y, vec![]);
+    for (i, p) in peaks.iter().enumerate() {
+        let mut bits = vec![0u64; words];
+        bits[i / 64] |= 1 << (i % 64);
+        reachable[*p] = bits;
+    }
+
+    for level in (0..9).rev() {
+        for (pos, _) in grid.pos_iter().filter(|(_, e)| e.0 == level) {
+            let mut bits = vec![0u64; words];
+            for next in neighbors(grid, pos, rule) {
+                for (a, b) in bits.iter_mut().zip(&reachable[next]) {
+                    *a |= b;
+                }
+            }
+            reachable[pos] = bits;
+        }
+    }
+
+    trailheads
+        .iter()
+        .map(|t| reachable[*t].iter().map(|w| w.count_ones() as usize).sum())
+        .collect()
 }
 
 #[aoc(day10, part1)]
 pub fn part1((grid, trailheads): &(Grid<Elevation>, Vec<Vec2i>)) -> usize {
-    let mut score = 0;
-    for t in trailheads {
-        let reachable = bfs_reach(*t, |&pos| neighbors(grid, pos));
-        score += reachable.filter(|&p| grid[p].0 == 9).count();
+    scores(grid, trailheads, &StepRule::default()).iter().sum()
+}
+
+/// Number of distinct trails starting at each trailhead.
+///
+/// For rules that only climb every cell's count is the sum of its neighbors' counts, computed in one sweep
+/// from elevation 9 down to 0 for all trailheads at once.
+pub fn ratings<C>(grid: &Grid<Elevation>, trailheads: &[Vec2i], rule: &StepRule) -> Vec<C>
+where
+    C: Clone + Zero + One + Add<Output = C>,
+{
+    if !rule.is_monotonic() {
+        return trailheads
+            .iter()
+            .map(|t| trails(grid, *t, rule).fold(C::zero(), |acc, _| acc + C::one()))
+            .collect();
+    }
+
+    let mut counts = Grid::new_from_element(grid.size_x, grid.size_y, C::zero());
+    for level in (0..=9).rev() {
+        for (pos, _) in grid.pos_iter().filter(|(_, e)| e.0 == level) {
+            counts[pos] = if level == 9 {
+                C::one()
+            } else {
+                neighbors(grid, pos, rule)
+                    .into_iter()
+                    .fold(C::zero(), |acc, next| acc + counts[next].clone())
+            };
+        }
     }
 
-    score
+    trailheads.iter().map(|t| counts[*t].clone()).collect()
 }
 
 #[aoc(day10, part2)]
-pub fn part2((grid, trailheads): &(Grid<Elevation>, Vec<Vec2i>)) -> usize {
-    let mut rating = 0;
-    for t in trailheads {
-        let paths = count_paths(*t, |&pos| neighbors(grid, pos), |&pos| grid[pos].0 == 9);
-        rating += paths;
-    }
-
-    rating
+pub fn part2((grid, trailheads): &(Grid<Elevation>, Vec<Vec2i>)) -> u128 {
+    ratings::<u128>(grid, trailheads, &StepRule::default())
+        .into_iter()
+        .sum()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use num::BigUint;
     use pretty_assertions::assert_eq;
 
     const INPUT: &str = r#"89010123
@@ -100,4 +244,135 @@ mod tests {
     fn test_part2_challenge() {
         assert_eq!(part2(&input_generator(CHALLENGE)), 16451);
     }
+
+    fn bfs_scores(grid: &Grid<Elevation>, trailheads: &[Vec2i], rule: &StepRule) -> Vec<usize> {
+        trailheads
+            .iter()
+            .map(|t| {
+                bfs_reach(*t, |&pos| neighbors(grid, pos, rule))
+                    .filter(|&p| grid[p].0 == 9)
+                    .count()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_scores_match_bfs() {
+        let (grid, trailheads) = input_generator(CHALLENGE);
+        for max_climb in 1..=3 {
+            for diagonal in [false, true] {
+                let rule = StepRule {
+                    max_climb,
+                    allow_descent: false,
+                    diagonal,
+                };
+                assert_eq!(
+                    scores(&grid, &trailheads, &rule),
+                    bfs_scores(&grid, &trailheads, &rule),
+                    "{rule:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_step_rule() {
+        let rule = StepRule {
+            max_climb: 2,
+            allow_descent: true,
+            diagonal: false,
+        };
+        assert!(rule.allows(Elevation(3), Elevation(5)));
+        assert!(rule.allows(Elevation(5), Elevation(3)));
+        assert!(!rule.allows(Elevation(3), Elevation(3)));
+        assert!(!rule.allows(Elevation(3), Elevation(6)));
+        assert!(!StepRule::default().allows(Elevation(5), Elevation(4)));
+
+        // going down lets the hiker cross the valley
+        let (grid, trailheads) = input_generator("01234565456789");
+        assert_eq!(scores(&grid, &trailheads, &StepRule::default()), [0]);
+        let rule = StepRule {
+            max_climb: 1,
+            allow_descent: true,
+            diagonal: true,
+        };
+        assert_eq!(scores(&grid, &trailheads, &rule), [1]);
+        assert_eq!(trails(&grid, trailheads[0], &rule).count(), 1);
+    }
+
+    #[test]
+    fn test_trails() {
+        let (grid, trailheads) = input_generator(INPUT);
+        let rule = StepRule::default();
+        let all = trailheads
+            .iter()
+            .flat_map(|t| trails(&grid, *t, &rule))
+            .collect_vec();
+        assert_eq!(all.len(), 81);
+        for trail in &all {
+            assert_eq!(trail.len(), 10);
+            for (i, pos) in trail.iter().enumerate() {
+                assert_eq!(grid[*pos].0 as usize, i);
+            }
+        }
+
+        let (grid, trailheads) = input_generator(CHALLENGE);
+        let count = trailheads
+            .iter()
+            .map(|t| trails(&grid, *t, &rule).count())
+            .sum::<usize>();
+        assert_eq!(count, 16451);
+    }
+
+    #[test]
+    fn test_ratings() {
+        let (grid, trailheads) = input_generator(CHALLENGE);
+        for max_climb in 1..=3 {
+            for diagonal in [false, true] {
+                let rule = StepRule {
+                    max_climb,
+                    allow_descent: false,
+                    diagonal,
+                };
+                let expected = trailheads
+                    .iter()
+                    .map(|t| {
+                        count_paths(
+                            *t,
+                            |&pos| neighbors(&grid, pos, &rule),
+                            |&pos| grid[pos].0 == 9,
+                        ) as u128
+                    })
+                    .collect_vec();
+                assert_eq!(ratings::<u128>(&grid, &trailheads, &rule), expected);
+                assert_eq!(
+                    ratings::<BigUint>(&grid, &trailheads, &rule),
+                    expected.into_iter().map(BigUint::from).collect_vec()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_ratings_with_descent() {
+        let (grid, trailheads) = input_generator("01234565456789\n11111156511111");
+        let rule = StepRule {
+            max_climb: 1,
+            allow_descent: true,
+            diagonal: false,
+        };
+        assert_eq!(
+            ratings::<u128>(&grid, &trailheads, &StepRule::default()),
+            [0]
+        );
+        let ratings = ratings::<u128>(&grid, &trailheads, &rule);
+        assert_eq!(ratings[0], 4);
+        assert_eq!(
+            ratings,
+            trailheads
+                .iter()
+                .map(|t| trails(&grid, *t, &rule).count() as u128)
+                .collect_vec()
+        );
+    }
 }
@@ -1,42 +1,57 @@
 use crate::common::parse_split_whitespace;
 use aoc_runner_derive::{aoc, aoc_generator};
+use indexmap::IndexSet;
 use itertools::Itertools;
-use rustc_hash::FxHashMap;
+use num::bigint::Sign;
+use num::{BigInt, BigUint, One, Zero};
+use rustc_hash::{FxBuildHasher, FxHashMap};
 use std::collections::HashMap;
-use tinyvec::{array_vec, ArrayVec};
+use tinyvec::{tiny_vec, TinyVec};
 
 #[aoc_generator(day11)]
 pub fn input_generator(input: &str) -> Vec<u64> {
     parse_split_whitespace(input).unwrap()
 }
 
-fn split(s: u64) -> ArrayVec<[u64; 2]> {
-    let mut r = array_vec!();
-    if s == 0 {
-        r.push(1);
-    } else {
-        let num_digits = s.ilog10() + 1;
-        if num_digits % 2 == 0 {
-            let p = 10u64.pow(num_digits / 2);
-            r.push(s / p);
-            r.push(s % p);
+/// How a single stone changes during one blink.
+pub trait Rules {
+    fn blink(&self, stone: u64) -> TinyVec<[u64; 2]>;
+}
+
+/// The rules from the puzzle: 0 becomes 1, an even number of digits splits the stone in half and
+/// everything else is multiplied by 2024.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Plutonian;
+
+impl Rules for Plutonian {
+    fn blink(&self, s: u64) -> TinyVec<[u64; 2]> {
+        let mut r = tiny_vec!();
+        if s == 0 {
+            r.push(1);
         } else {
-            r.push(s * 2024);
+            let num_digits = s.ilog10() + 1;
+            if num_digits.is_multiple_of(2) {
+                let p = 10u64.pow(num_digits / 2);
+                r.push(s / p);
+                r.push(s % p);
+            } else {
+                r.push(s * 2024);
+            }
         }
-    }
 
-    r
+        r
+    }
 }
 
-fn solve<const N: usize>(stones: &[u64]) -> usize {
+pub fn count_stones(rules: &impl Rules, stones: &[u64], blinks: usize) -> usize {
     let mut cache: FxHashMap<(u64, usize), usize> = HashMap::default();
 
-    let mut stack = stones.iter().map(|&n| (n, N)).collect_vec();
+    let mut stack = stones.iter().map(|&n| (n, blinks)).collect_vec();
     while let Some((s, steps)) = stack.pop() {
         if steps == 0 {
             cache.insert((s, steps), 1);
         } else {
-            let split = split(s);
+            let split = rules.blink(s);
             let next_steps = steps - 1;
 
             let mut amount = 0;
@@ -61,17 +76,403 @@ fn solve<const N: usize>(stones: &[u64]) -> usize {
         }
     }
 
-    stones.iter().map(|&n| cache[&(n, N)]).sum()
+    stones.iter().map(|&n| cache[&(n, blinks)]).sum()
+}
+
+/// How often each stone value occurs after the given number of blinks.
+///
+/// The order of the stones is lost, but equal stones only have to be blinked once.
+pub fn evolve(rules: &impl Rules, stones: &[u64], blinks: usize) -> FxHashMap<u64, u128> {
+    let mut counts: FxHashMap<u64, u128> = FxHashMap::default();
+    for &s in stones {
+        *counts.entry(s).or_default() += 1;
+    }
+
+    for _ in 0..blinks {
+        let mut next: FxHashMap<u64, u128> = FxHashMap::default();
+        for (s, count) in counts {
+            for child in rules.blink(s) {
+                *next.entry(child).or_default() += count;
+            }
+        }
+        counts = next;
+    }
+
+    counts
+}
+
+/// The transitions of one blink between all stone values reachable from some initial stones.
+///
+/// Row `i` of the transition matrix lists the values a stone with value `i` turns into,
+/// at most a handful per row, so only those are stored.
+#[derive(Debug, Clone)]
+pub struct Transitions {
+    values: IndexSet<u64, FxBuildHasher>,
+    children: Vec<TinyVec<[usize; 2]>>,
+}
+
+impl Transitions {
+    /// Collects the closed set of stone values, returns `None` if it has more than `limit` elements.
+    pub fn new(rules: &impl Rules, stones: &[u64], limit: usize) -> Option<Self> {
+        let mut values: IndexSet<u64, FxBuildHasher> = stones.iter().copied().collect();
+        let mut children = vec![];
+        let mut i = 0;
+        while i < values.len() {
+            let blinked = rules
+                .blink(values[i])
+                .into_iter()
+                .map(|child| values.insert_full(child).0)
+                .collect();
+            if values.len() > limit {
+                return None;
+            }
+
+            children.push(blinked);
+            i += 1;
+        }
+
+        Some(Self { values, children })
+    }
+
+    /// All stone values in the closed set.
+    pub fn values(&self) -> impl Iterator<Item = u64> + '_ {
+        self.values.iter().copied()
+    }
+
+    /// Multiplies the transition matrix with a column vector, `v[i]` becomes the sum over the children of `i`.
+    fn apply<T: Clone + Zero>(&self, v: &[T]) -> Vec<T> {
+        self.children
+            .iter()
+            .map(|children| {
+                children
+                    .iter()
+                    .fold(T::zero(), |acc, &child| acc + v[child].clone())
+            })
+            .collect()
+    }
+
+    fn indices(&self, stones: &[u64]) -> Vec<usize> {
+        stones
+            .iter()
+            .map(|s| self.values.get_index_of(s).unwrap())
+            .collect()
+    }
+
+    /// Number of stones after each of the first `len` blinks, modulo `p`.
+    fn totals_mod(&self, stones: &[usize], len: usize, p: u64) -> Vec<u64> {
+        let add = |a: u64, b: u64| if a + b >= p { a + b - p } else { a + b };
+        let mut counts = vec![1; self.children.len()];
+        let mut next = vec![0; self.children.len()];
+        let mut totals = Vec::with_capacity(len);
+        for _ in 0..len {
+            totals.push(stones.iter().fold(0, |acc, &s| add(acc, counts[s])));
+            for (count, children) in next.iter_mut().zip(&self.children) {
+                *count = 0;
+                for &child in children {
+                    *count = add(*count, counts[child]);
+                }
+            }
+            std::mem::swap(&mut counts, &mut next);
+        }
+        totals
+    }
+
+    /// The shortest linear recurrence of the number of stones after each blink, as a monic
+    /// polynomial with the lowest degree first.
+    ///
+    /// The totals satisfy the characteristic polynomial of the transition matrix, so for `n` values
+    /// the recurrence has at most `n` terms and Berlekamp-Massey on the first `2n` totals finds it
+    /// modulo a prime. The residues for several primes are combined until the integer coefficients
+    /// stop changing. Values that only show up during the first few blinks barely add to it, so it
+    /// is often much shorter than `n`.
+    pub fn recurrence(&self, stones: &[u64]) -> Vec<BigInt> {
+        let stones = self.indices(stones);
+        let len = 2 * self.values.len();
+        let mut modulus = BigInt::one();
+        let mut residues = vec![];
+        let mut previous = None;
+        for p in PRIMES {
+            let c = berlekamp_massey(&self.totals_mod(&stones, len, p), p);
+            // modulo an unlucky prime the recurrence gets shorter, those residues are useless
+            if c.len() < residues.len() {
+                continue;
+            } else if c.len() > residues.len() {
+                modulus = BigInt::one();
+                residues = vec![BigInt::zero(); c.len()];
+                previous = None;
+            }
+
+            // chinese remainder theorem, the connection polynomial is the reversed recurrence
+            let inverse = pow_mod((&modulus % p).try_into().unwrap(), p - 2, p);
+            for (r, &c) in residues.iter_mut().zip(c.iter().rev()) {
+                let r_mod: u64 = (&*r % p).try_into().unwrap();
+                let k = mul_mod((c + p - r_mod) % p, inverse, p);
+                *r += &modulus * k;
+            }
+            modulus *= p;
+
+            let half = &modulus / 2;
+            let lifted = residues
+                .iter()
+                .map(|r| if *r > half { r - &modulus } else { r.clone() })
+                .collect_vec();
+            if previous.as_ref() == Some(&lifted) {
+                return lifted;
+            }
+            previous = Some(lifted);
+        }
+
+        panic!("recurrence coefficients are too large")
+    }
+
+    /// Number of stones after the given number of blinks.
+    ///
+    /// The totals satisfy the recurrence `q`, so the total after `blinks` blinks is
+    /// `x^blinks mod q` evaluated on the first totals. That remainder is computed by repeated
+    /// squaring, which only needs the first `deg q` totals.
+    /// All stones have to be part of the closed set.
+    pub fn count(&self, stones: &[u64], blinks: u64) -> BigUint {
+        let q = self.recurrence(stones);
+        let d = q.len() - 1;
+        if d == 0 {
+            // no stones at all
+            return BigUint::zero();
+        }
+
+        let stones = self.indices(stones);
+        // counts[i] is the number of stones value i turns into after the current number of blinks
+        let mut counts = vec![BigUint::one(); self.values.len()];
+        let mut totals = vec![];
+        for _ in 0..d.min(blinks as usize + 1) {
+            totals.push(stones.iter().map(|&s| &counts[s]).sum::<BigUint>());
+            counts = self.apply(&counts);
+        }
+
+        if blinks < d as u64 {
+            return totals.pop().unwrap();
+        }
+
+        let remainder = power_mod(blinks, &q);
+        remainder
+            .into_iter()
+            .zip(totals)
+            .map(|(r, total)| r * BigInt::from(total))
+            .sum::<BigInt>()
+            .to_biguint()
+            .unwrap()
+    }
+}
+
+/// Primes just below `2^62`, so the sum of two residues still fits into a `u64`.
+const PRIMES: [u64; 4] = [
+    (1 << 62) - 57,
+    (1 << 62) - 87,
+    (1 << 62) - 117,
+    (1 << 62) - 143,
+];
+
+fn mul_mod(a: u64, b: u64, p: u64) -> u64 {
+    (a as u128 * b as u128 % p as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut e: u64, p: u64) -> u64 {
+    let mut result = 1;
+    while e > 0 {
+        if e & 1 == 1 {
+            result = mul_mod(result, base, p);
+        }
+        base = mul_mod(base, base, p);
+        e >>= 1;
+    }
+    result
+}
+
+/// Shortest connection polynomial `c` with `c[0] = 1` and `sum(c[i] * seq[k - i]) = 0 mod p`
+/// for all `k >= c.len() - 1`.
+fn berlekamp_massey(seq: &[u64], p: u64) -> Vec<u64> {
+    let mut c = vec![1];
+    let mut b = vec![1];
+    let mut l = 0;
+    let mut m = 1;
+    let mut last_discrepancy = 1;
+    for k in 0..seq.len() {
+        let d = c
+            .iter()
+            .take(l + 1)
+            .enumerate()
+            .fold(0, |acc, (i, &ci)| (acc + mul_mod(ci, seq[k - i], p)) % p);
+        if d == 0 {
+            m += 1;
+            continue;
+        }
+
+        let factor = mul_mod(d, pow_mod(last_discrepancy, p - 2, p), p);
+        let t = (2 * l <= k).then(|| c.clone());
+        if c.len() < b.len() + m {
+            c.resize(b.len() + m, 0);
+        }
+        for (i, &bi) in b.iter().enumerate() {
+            c[i + m] = (c[i + m] + p - mul_mod(factor, bi, p)) % p;
+        }
+
+        if let Some(t) = t {
+            l = k + 1 - l;
+            b = t;
+            last_discrepancy = d;
+            m = 1;
+        } else {
+            m += 1;
+        }
+    }
+
+    c.resize(l + 1, 0);
+    c
+}
+
+/// `x^e mod p` for a monic polynomial `p` of degree `n`, the result has `n` coefficients.
+fn power_mod(e: u64, p: &[BigInt]) -> Vec<BigInt> {
+    let n = p.len() - 1;
+    // the quotient of a division by p is the reversed dividend times the power series inverse of
+    // the reversed p, cut off after as many terms as the quotient has
+    let inverse = inverse_series(&p.iter().rev().cloned().collect_vec(), n.saturating_sub(1));
+    let reduce = |r: Vec<BigInt>| {
+        if r.len() <= n {
+            return r;
+        }
+
+        let m = r.len() - n;
+        let mut quotient = mul(
+            &r.iter().rev().take(m).cloned().collect_vec(),
+            &inverse[..m],
+        );
+        quotient.truncate(m);
+        quotient.reverse();
+        r.into_iter()
+            .zip(mul(&quotient, p))
+            .take(n)
+            .map(|(a, b)| a - b)
+            .collect()
+    };
+
+    let mut result = vec![BigInt::zero(); n];
+    result[0] = BigInt::one();
+    for bit in (0..u64::BITS - e.leading_zeros()).rev() {
+        result = reduce(mul(&result, &result));
+
+        if (e >> bit) & 1 == 1 {
+            let t = result.pop().unwrap();
+            result.insert(0, BigInt::zero());
+            for (r, c) in result.iter_mut().zip(p) {
+                *r -= &t * c;
+            }
+        }
+    }
+
+    result
+}
+
+/// The first `len` coefficients of `1 / f` for a power series `f` starting with 1.
+fn inverse_series(f: &[BigInt], len: usize) -> Vec<BigInt> {
+    let mut g = vec![BigInt::one()];
+    for i in 1..len {
+        let c = (1..=i.min(f.len() - 1))
+            .map(|j| &f[j] * &g[i - j])
+            .sum::<BigInt>();
+        g.push(-c);
+    }
+    g.truncate(len);
+    g
+}
+
+/// Multiplies two polynomials.
+///
+/// Long ones are packed into single integers, each coefficient in its own block of bits, so that
+/// one big multiplication does the work of all the small ones.
+fn mul(a: &[BigInt], b: &[BigInt]) -> Vec<BigInt> {
+    if a.is_empty() || b.is_empty() {
+        return vec![];
+    }
+    if a.len().min(b.len()) < 64 {
+        let mut product = vec![BigInt::zero(); a.len() + b.len() - 1];
+        if std::ptr::eq(a, b) {
+            // every product of two different coefficients shows up twice in a square
+            for (i, x) in a.iter().enumerate().filter(|(_, x)| !x.is_zero()) {
+                for (j, y) in a.iter().enumerate().skip(i + 1) {
+                    product[i + j] += x * y;
+                }
+            }
+            for (i, x) in a.iter().enumerate() {
+                product[2 * i] <<= 1;
+                if 2 * i + 1 < product.len() {
+                    product[2 * i + 1] <<= 1;
+                }
+                product[2 * i] += x * x;
+            }
+        } else {
+            for (i, x) in a.iter().enumerate().filter(|(_, x)| !x.is_zero()) {
+                for (j, y) in b.iter().enumerate() {
+                    product[i + j] += x * y;
+                }
+            }
+        }
+        return product;
+    }
+
+    let bits = |p: &[BigInt]| p.iter().map(BigInt::bits).max().unwrap();
+    // coefficients of the product are below min(len) * 2^(bits(a) + bits(b)), plus a sign bit
+    let width =
+        (bits(a) + bits(b) + u64::from(a.len().min(b.len()).ilog2()) + 3).div_ceil(32) as usize;
+    let pack = |p: &[BigInt]| {
+        let mut positive = vec![0; p.len() * width];
+        let mut negative = vec![0; p.len() * width];
+        for (i, c) in p.iter().enumerate() {
+            let digits = if c.sign() == Sign::Minus {
+                &mut negative
+            } else {
+                &mut positive
+            };
+            for (j, digit) in c.iter_u32_digits().enumerate() {
+                digits[i * width + j] = digit;
+            }
+        }
+        BigInt::from(BigUint::new(positive)) - BigInt::from(BigUint::new(negative))
+    };
+    let product = pack(a) * pack(b);
+    let negative = product.sign() == Sign::Minus;
+    let digits = product.magnitude().to_u32_digits();
+
+    // unpack into digits in (-2^(32 * width - 1), 2^(32 * width - 1)), borrowing from the next one
+    let base = BigInt::one() << (32 * width);
+    let mut carry = false;
+    (0..a.len() + b.len() - 1)
+        .map(|k| {
+            let start = (k * width).min(digits.len());
+            let end = ((k + 1) * width).min(digits.len());
+            let mut c = BigInt::from(BigUint::from_slice(&digits[start..end]));
+            if carry {
+                c += 1;
+            }
+            carry = c.bits() >= 32 * width as u64;
+            if carry {
+                c -= &base;
+            }
+            if negative {
+                -c
+            } else {
+                c
+            }
+        })
+        .collect()
 }
 
 #[aoc(day11, part1)]
 pub fn part1(input: &[u64]) -> usize {
-    solve::<25>(input)
+    count_stones(&Plutonian, input, 25)
 }
 
 #[aoc(day11, part2)]
 pub fn part2(input: &[u64]) -> usize {
-    solve::<75>(input)
+    count_stones(&Plutonian, input, 75)
 }
 
 #[cfg(test)]
@@ -85,4 +486,118 @@ mod tests {
     fn test_part1() {
         assert_eq!(part1(&input_generator(INPUT)), 55312);
     }
+
+    #[test]
+    fn test_evolve() {
+        let stones = input_generator(INPUT);
+        let expected = "2097446912 14168 4048 2 0 2 4 40 48 2024 40 48 80 96 2 8 6 7 6 0 3 2"
+            .split_whitespace()
+            .map(|s| s.parse::<u64>().unwrap())
+            .counts()
+            .into_iter()
+            .map(|(s, count)| (s, count as u128))
+            .collect::<FxHashMap<_, _>>();
+        assert_eq!(evolve(&Plutonian, &stones, 6), expected);
+        assert_eq!(
+            evolve(&Plutonian, &stones, 25).values().sum::<u128>(),
+            55312
+        );
+        assert_eq!(
+            evolve(&Plutonian, &stones, 75).values().sum::<u128>(),
+            count_stones(&Plutonian, &stones, 75) as u128
+        );
+    }
+
+    struct Triple;
+
+    impl Rules for Triple {
+        fn blink(&self, stone: u64) -> TinyVec<[u64; 2]> {
+            tiny_vec!([u64; 2] => stone, stone + 1, stone + 2)
+        }
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        assert_eq!(count_stones(&Triple, &[0, 5], 10), 2 * 3usize.pow(10));
+        let counts = evolve(&Triple, &[0], 2);
+        assert_eq!(counts[&2], 3);
+        assert_eq!(counts.values().sum::<u128>(), 9);
+        assert_eq!(count_stones(&Plutonian, &[0], 0), 1);
+    }
+
+    #[test]
+    fn test_transitions() {
+        let stones = input_generator(INPUT);
+        let transitions = Transitions::new(&Plutonian, &stones, 10_000).unwrap();
+        assert_eq!(transitions.values().count(), 76);
+        assert_eq!(transitions.count(&stones, 0), BigUint::from(2u8));
+        assert_eq!(transitions.count(&stones, 6), BigUint::from(22u8));
+        assert_eq!(transitions.count(&stones, 25), BigUint::from(55312u32));
+        assert_eq!(
+            transitions.count(&stones, 75),
+            BigUint::from(count_stones(&Plutonian, &stones, 75))
+        );
+        assert_eq!(
+            transitions.count(&[0, 17], 100),
+            BigUint::from(evolve(&Plutonian, &[0, 17], 100).values().sum::<u128>())
+        );
+
+        let expected = evolve_big(&[0], 1000);
+        assert!(expected.bits() > 500);
+        assert_eq!(transitions.count(&[0], 1000), expected);
+    }
+
+    /// Number of stones after blinking with big counts, without any closed set.
+    fn evolve_big(stones: &[u64], blinks: usize) -> BigUint {
+        let mut counts = stones.iter().map(|&s| (s, BigUint::one())).collect_vec();
+        for _ in 0..blinks {
+            counts = counts
+                .into_iter()
+                .flat_map(|(s, n)| Plutonian.blink(s).into_iter().map(move |c| (c, n.clone())))
+                .into_grouping_map()
+                .sum()
+                .into_iter()
+                .collect();
+        }
+        counts.into_iter().map(|(_, n)| n).sum()
+    }
+
+    #[test]
+    fn test_transitions_many_values() {
+        // the four digit stones split into two digit ones right away, so most of the values
+        // only show up once and the recurrence stays short
+        let stones = (1000..3000).collect_vec();
+        let transitions = Transitions::new(&Plutonian, &stones, 10_000).unwrap();
+        assert_eq!(transitions.values().count(), 2118);
+        assert_eq!(transitions.recurrence(&stones).len(), 41);
+
+        let expected = evolve_big(&stones, 10_000);
+        assert!(expected.bits() > 6000);
+        assert_eq!(transitions.count(&stones, 10_000), expected);
+    }
+
+    #[test]
+    fn test_recurrence() {
+        // 0 -> 1 -> 2024 -> 20 24 -> 2 0 2 4 -> ...
+        let transitions = Transitions::new(&Plutonian, &[0], 100).unwrap();
+        let q = transitions.recurrence(&[0]);
+        assert_eq!(q.len(), 39);
+        assert_eq!(q[38], BigInt::one());
+
+        // the stone counts satisfy the recurrence
+        let counts = (0..60)
+            .map(|blinks| BigInt::from(count_stones(&Plutonian, &[0], blinks)))
+            .collect_vec();
+        for start in 0..20 {
+            let value: BigInt = q.iter().zip(&counts[start..]).map(|(c, a)| c * a).sum();
+            assert!(value.is_zero());
+        }
+    }
+
+    #[test]
+    fn test_transitions_limit() {
+        assert!(Transitions::new(&Triple, &[0], 1000).is_none());
+        assert!(Transitions::new(&Plutonian, &[0], 53).is_none());
+        assert!(Transitions::new(&Plutonian, &[0], 54).is_some());
+    }
 }
@@ -3,84 +3,318 @@ use aoc_runner_derive::{aoc, aoc_generator};
 use itertools::Itertools;
 use pathfinding::prelude::*;
 use rustc_hash::{FxHashMap, FxHashSet};
-use tinyvec::ArrayVec;
 
 #[aoc_generator(day12)]
 pub fn input_generator(input: &str) -> Grid<char> {
     input.parse().unwrap()
 }
 
-fn neighbors(grid: &Grid<char>, pos: Vec2i) -> impl IntoIterator<Item = Vec2i> {
-    let c = grid[pos];
-    Direction::VALUES
-        .into_iter()
-        .map(|dir| dir.offset(&pos))
-        .filter(|pos| grid.in_bounds(pos) && grid[*pos] == c)
-        .collect::<ArrayVec<[Vec2i; 4]>>()
+struct UnionFind {
+    parent: Vec<usize>,
 }
 
-#[aoc(day12, part1)]
-pub fn part1(input: &Grid<char>) -> usize {
-    let starts = input.pos_iter().map(|(p, _)| p).collect_vec();
-    let components = connected_components(&starts, |&p| neighbors(input, p));
-    components
-        .iter()
-        .map(|c| {
-            let area = c.len();
-            let mut perimeter = 0;
-            for &p in c {
-                for d in Direction::VALUES {
-                    if !c.contains(&(d.offset(&p))) {
-                        perimeter += 1;
-                    }
+impl UnionFind {
+    fn new(n: usize) -> Self {
+        Self {
+            parent: (0..n).collect(),
+        }
+    }
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            self.parent[x] = self.parent[self.parent[x]];
+            x = self.parent[x];
+        }
+        x
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let a = self.find(a);
+        let b = self.find(b);
+        self.parent[a.max(b)] = a.min(b);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub plant: char,
+    pub area: usize,
+    pub perimeter: usize,
+    pub sides: usize,
+    /// number of separate areas of other plants that are completely surrounded by this region
+    pub holes: usize,
+    /// top left corner of the bounding box
+    pub min: Vec2i,
+    /// bottom right corner of the bounding box, inclusive
+    pub max: Vec2i,
+}
+
+impl Region {
+    pub fn price(&self) -> usize {
+        self.area * self.perimeter
+    }
+
+    pub fn bulk_price(&self) -> usize {
+        self.area * self.sides
+    }
+}
+
+/// All regions of a garden together with the region of every plot.
+#[derive(Debug, Clone)]
+pub struct Garden {
+    labels: Grid<usize>,
+    regions: Vec<Region>,
+}
+
+impl Garden {
+    pub fn new(grid: &Grid<char>) -> Self {
+        let index = |pos: Vec2i| pos.y as usize * grid.size_x + pos.x as usize;
+        let mut uf = UnionFind::new(grid.size_x * grid.size_y);
+        for (pos, c) in grid.pos_iter() {
+            for d in [Direction::East, Direction::South] {
+                let next = d.offset(&pos);
+                if grid.in_bounds(&next) && grid[next] == *c {
+                    uf.union(index(pos), index(next));
                 }
             }
+        }
+
+        let mut regions: Vec<Region> = vec![];
+        let mut ids = FxHashMap::default();
+        let mut labels = Grid::new_from_element(grid.size_x, grid.size_y, 0);
+        for (pos, c) in grid.pos_iter() {
+            let id = *ids.entry(uf.find(index(pos))).or_insert_with(|| {
+                regions.push(Region {
+                    plant: *c,
+                    area: 0,
+                    perimeter: 0,
+                    sides: 0,
+                    holes: 0,
+                    min: pos,
+                    max: pos,
+                });
+                regions.len() - 1
+            });
+            labels[pos] = id;
+        }
 
-            area * perimeter
+        let mut garden = Self { labels, regions };
+        for (pos, &id) in garden.labels.pos_iter() {
+            let perimeter = Direction::VALUES
+                .into_iter()
+                .filter(|d| garden.labels_at(d.offset(&pos)) != Some(id))
+                .count();
+
+            let region = &mut garden.regions[id];
+            region.area += 1;
+            region.perimeter += perimeter;
+            region.min = region.min.inf(&pos);
+            region.max = region.max.sup(&pos);
+        }
+
+        // every side ends in exactly one corner, so count the corners in all 2x2 windows
+        // holes from the euler characteristic V - E + F = 1 - holes of the plots as closed squares
+        let mut vertices = vec![0; garden.regions.len()];
+        for y in 0..=grid.size_y as i64 {
+            for x in 0..=grid.size_x as i64 {
+                let window = [(-1, -1), (0, -1), (-1, 0), (0, 0)]
+                    .map(|(dx, dy)| garden.labels_at(Vec2i::new(x + dx, y + dy)));
+                for id in window.iter().flatten().unique() {
+                    let [tl, tr, bl, br] = window.map(|l| l == Some(*id));
+                    garden.regions[*id].sides +=
+                        match [tl, tr, bl, br].iter().filter(|&&b| b).count() {
+                            1 | 3 => 1,
+                            2 if tl == br => 2,
+                            _ => 0,
+                        };
+                    vertices[*id] += 1;
+                }
+            }
+        }
+        for (region, v) in garden.regions.iter_mut().zip(vertices) {
+            let edges = 2 * region.area + region.perimeter / 2;
+            region.holes = 1 + edges - v - region.area;
+        }
+
+        garden
+    }
+
+    fn labels_at(&self, pos: Vec2i) -> Option<usize> {
+        self.labels.in_bounds(&pos).then(|| self.labels[pos])
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Index of the region the plot belongs to.
+    pub fn region_at(&self, pos: Vec2i) -> usize {
+        self.labels[pos]
+    }
+
+    /// Indices of all regions that lie in one of the holes of the given region.
+    pub fn contained_regions(&self, id: usize) -> Vec<usize> {
+        let region = &self.regions[id];
+        if region.holes == 0 {
+            return vec![];
+        }
+
+        // everything not in the region that can be reached from outside of the bounding box
+        let min = region.min.add_scalar(-1);
+        let max = region.max.add_scalar(1);
+        let outside = bfs_reach(min, |&pos| {
+            Direction::VALUES
+                .into_iter()
+                .map(move |d| d.offset(&pos))
+                .filter(|next| {
+                    next.x >= min.x
+                        && next.y >= min.y
+                        && next.x <= max.x
+                        && next.y <= max.y
+                        && self.labels_at(*next) != Some(id)
+                })
         })
-        .sum()
-}
+        .collect::<FxHashSet<_>>();
 
-#[aoc(day12, part2)]
-pub fn part2(input: &Grid<char>) -> usize {
-    let starts = input.pos_iter().map(|(p, _)| p).collect_vec();
-    let components = connected_components(&starts, |&p| neighbors(input, p));
-    components
-        .iter()
-        .map(|c| {
-            let area = c.len();
-
-            let mut outer_edges: FxHashMap<Direction, FxHashSet<Vec2i>> = FxHashMap::default();
-            for &p in c {
-                for d in Direction::VALUES {
-                    if !c.contains(&(d.offset(&p))) {
-                        outer_edges.entry(d).or_default().insert(p);
+        (region.min.y..=region.max.y)
+            .flat_map(|y| (region.min.x..=region.max.x).map(move |x| Vec2i::new(x, y)))
+            .filter(|pos| !outside.contains(pos))
+            .map(|pos| self.labels[pos])
+            .filter(|&other| other != id)
+            .unique()
+            .sorted_unstable()
+            .collect()
+    }
+
+    /// The boundary of a region as closed polygons of lattice points.
+    ///
+    /// Plots that only touch at a corner are kept apart, so every hole gets its own ring.
+    pub fn outline(&self, id: usize) -> Outline {
+        let region = &self.regions[id];
+        // directed unit edges with the region on their right, keyed by their start point
+        let mut edges = vec![];
+        for y in region.min.y..=region.max.y {
+            for x in region.min.x..=region.max.x {
+                let pos = Vec2i::new(x, y);
+                if self.labels[pos] != id {
+                    continue;
+                }
+
+                for (d, corner) in [
+                    (Direction::North, Vec2i::new(x, y)),
+                    (Direction::East, Vec2i::new(x + 1, y)),
+                    (Direction::South, Vec2i::new(x + 1, y + 1)),
+                    (Direction::West, Vec2i::new(x, y + 1)),
+                ] {
+                    if self.labels_at(d.offset(&pos)) != Some(id) {
+                        edges.push((corner, d.rotate_cw()));
                     }
                 }
             }
+        }
 
-            let mut sides = 0;
-            for (&normal, edges) in &mut outer_edges {
-                while let Some(&edge) = edges.iter().next() {
-                    sides += 1;
-                    edges.remove(&edge);
-
-                    for parallel in [normal.rotate_cw(), normal.rotate_ccw()] {
-                        let mut i = 1;
-                        loop {
-                            let next = parallel.offset_with_amount(&edge, i);
-                            if !edges.remove(&next) {
-                                break;
-                            }
-
-                            i += 1;
-                        }
-                    }
+        let mut remaining: FxHashSet<_> = edges.iter().copied().collect();
+        let mut rings = vec![];
+        for start in edges {
+            if !remaining.remove(&start) {
+                continue;
+            }
+
+            let mut walked = vec![start];
+            let (mut pos, mut dir) = start;
+            loop {
+                pos = dir.offset(&pos);
+                if pos == start.0 {
+                    break;
                 }
+
+                // prefer turning left, away from the region, which keeps the rings around
+                // diagonally touching holes apart
+                dir = [dir.rotate_ccw(), dir, dir.rotate_cw()]
+                    .into_iter()
+                    .find(|&d| remaining.remove(&(pos, d)))
+                    .unwrap();
+                walked.push((pos, dir));
             }
 
-            area * sides
-        })
+            let last = walked.last().unwrap().1;
+            let mut ring = walked
+                .iter()
+                .zip(std::iter::once(last).chain(walked.iter().map(|&(_, d)| d)))
+                .filter(|((_, d), previous)| d != previous)
+                .map(|((pos, _), _)| *pos)
+                .collect_vec();
+            let first = ring.iter().position_min_by_key(|p| (p.y, p.x)).unwrap();
+            ring.rotate_left(first);
+            rings.push(ring);
+        }
+        rings.sort_unstable_by_key(|ring| (ring[0].y, ring[0].x));
+
+        // the outer ring is the only one going clockwise
+        let outer = rings
+            .iter()
+            .position(|ring| shoelace_area(ring) > 0)
+            .unwrap();
+        let outer = rings.remove(outer);
+        Outline {
+            outer,
+            holes: rings,
+        }
+    }
+}
+
+/// The boundary of a region, with corners only at the points where the boundary turns.
+///
+/// Every ring starts at its top left corner, the holes are ordered by that corner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outline {
+    /// clockwise on screen, with y pointing down
+    pub outer: Vec<Vec2i>,
+    /// counterclockwise on screen, one ring per hole
+    pub holes: Vec<Vec<Vec2i>>,
+}
+
+impl Outline {
+    pub fn rings(&self) -> impl Iterator<Item = &[Vec2i]> {
+        std::iter::once(self.outer.as_slice()).chain(self.holes.iter().map(Vec::as_slice))
+    }
+
+    /// Area inside the outer ring without the holes.
+    pub fn area(&self) -> i64 {
+        self.rings().map(shoelace_area).sum()
+    }
+
+    /// Path data for an svg `<path>` element, the holes are cut out with either fill rule.
+    pub fn svg_path(&self) -> String {
+        self.rings()
+            .map(|ring| {
+                let points = ring.iter().map(|p| format!("{} {}", p.x, p.y)).join(" L ");
+                format!("M {points} Z")
+            })
+            .join(" ")
+    }
+}
+
+/// Signed area of a closed polygon, positive if it goes clockwise with y pointing down.
+pub fn shoelace_area(ring: &[Vec2i]) -> i64 {
+    ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| a.x * b.y - b.x * a.y)
+        .sum::<i64>()
+        / 2
+}
+
+#[aoc(day12, part1)]
+pub fn part1(input: &Grid<char>) -> usize {
+    Garden::new(input).regions().iter().map(Region::price).sum()
+}
+
+#[aoc(day12, part2)]
+pub fn part2(input: &Grid<char>) -> usize {
+    Garden::new(input)
+        .regions()
+        .iter()
+        .map(Region::bulk_price)
         .sum()
 }
 
@@ -154,4 +388,120 @@ AAAAAA"#;
     fn test_part2_4() {
         assert_eq!(part2(&input_generator(INPUT_5)), 368);
     }
+
+    #[test]
+    fn test_regions() {
+        let garden = Garden::new(&input_generator(INPUT_1));
+        let summary = garden
+            .regions()
+            .iter()
+            .map(|r| (r.plant, r.area, r.perimeter, r.sides, r.holes))
+            .collect_vec();
+        assert_eq!(
+            summary,
+            [
+                ('A', 4, 10, 4, 0),
+                ('B', 4, 8, 4, 0),
+                ('C', 4, 10, 8, 0),
+                ('D', 1, 4, 4, 0),
+                ('E', 3, 8, 4, 0),
+            ]
+        );
+        let c = &garden.regions()[garden.region_at(Vec2i::new(2, 1))];
+        assert_eq!((c.min, c.max), (Vec2i::new(2, 1), Vec2i::new(3, 3)));
+
+        let garden = Garden::new(&input_generator(INPUT_3));
+        assert_eq!(garden.regions().len(), 11);
+    }
+
+    #[test]
+    fn test_holes() {
+        let garden = Garden::new(&input_generator(INPUT_2));
+        let o = garden.region_at(Vec2i::new(0, 0));
+        assert_eq!(garden.regions()[o].holes, 4);
+        assert_eq!(garden.regions()[o].perimeter, 36);
+        let contained = garden.contained_regions(o);
+        assert_eq!(contained.len(), 4);
+        assert!(contained.iter().all(|&r| garden.regions()[r].plant == 'X'));
+        assert!(garden.contained_regions(contained[0]).is_empty());
+
+        // the two B regions only touch at a corner, so they are separate holes
+        let garden = Garden::new(&input_generator(INPUT_5));
+        let a = garden.region_at(Vec2i::new(0, 0));
+        assert_eq!(garden.regions()[a].holes, 2);
+        assert_eq!(garden.contained_regions(a).len(), 2);
+
+        // a hole with two regions in it
+        let garden = Garden::new(&input_generator("AAAA\nABCA\nAAAA"));
+        let a = garden.region_at(Vec2i::new(0, 0));
+        assert_eq!(garden.regions()[a].holes, 1);
+        assert_eq!(garden.contained_regions(a), [1, 2]);
+
+        // open to the border, so not a hole
+        let garden = Garden::new(&input_generator("AAA\nABA\nABA"));
+        assert_eq!(garden.regions()[0].holes, 0);
+        assert!(garden.contained_regions(0).is_empty());
+    }
+
+    #[test]
+    fn test_corners() {
+        let garden = Garden::new(&input_generator(INPUT_5));
+        let sides = garden.regions().iter().map(|r| r.sides).collect_vec();
+        assert_eq!(sides, [12, 4, 4]);
+
+        let garden = Garden::new(&input_generator(INPUT_4));
+        let e = &garden.regions()[garden.region_at(Vec2i::new(0, 0))];
+        assert_eq!(e.sides, 12);
+    }
+
+    #[test]
+    fn test_outline() {
+        let garden = Garden::new(&input_generator(INPUT_1));
+        let c = garden.region_at(Vec2i::new(2, 1));
+        let outline = garden.outline(c);
+        assert_eq!(
+            outline.outer,
+            [
+                (2, 1),
+                (3, 1),
+                (3, 2),
+                (4, 2),
+                (4, 4),
+                (3, 4),
+                (3, 3),
+                (2, 3)
+            ]
+            .map(|(x, y)| Vec2i::new(x, y))
+        );
+        assert!(outline.holes.is_empty());
+        assert_eq!(outline.area(), 4);
+        assert_eq!(
+            outline.svg_path(),
+            "M 2 1 L 3 1 L 3 2 L 4 2 L 4 4 L 3 4 L 3 3 L 2 3 Z"
+        );
+
+        let garden = Garden::new(&input_generator(INPUT_2));
+        let outline = garden.outline(garden.region_at(Vec2i::new(0, 0)));
+        assert_eq!(outline.outer.len(), 4);
+        assert_eq!(outline.holes.len(), 4);
+        assert!(outline.holes.iter().all(|hole| shoelace_area(hole) == -1));
+        assert_eq!(
+            outline.holes[0],
+            [(1, 1), (1, 2), (2, 2), (2, 1)].map(|(x, y)| Vec2i::new(x, y))
+        );
+        assert_eq!(outline.area(), 21);
+    }
+
+    #[test]
+    fn test_outline_matches_regions() {
+        for input in [INPUT_1, INPUT_2, INPUT_3, INPUT_4, INPUT_5] {
+            let garden = Garden::new(&input_generator(input));
+            for (id, region) in garden.regions().iter().enumerate() {
+                let outline = garden.outline(id);
+                assert_eq!(outline.area(), region.area as i64);
+                assert_eq!(outline.holes.len(), region.holes);
+                assert_eq!(outline.rings().map(<[_]>::len).sum::<usize>(), region.sides);
+            }
+        }
+    }
 }
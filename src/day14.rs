@@ -1,6 +1,8 @@
-use crate::common::{parse_vec, Vec2f, Vec2i};
+use crate::common::{parse_vec, Vec2i};
 use aoc_runner_derive::{aoc, aoc_generator};
 use itertools::Itertools;
+use num::integer::ExtendedGcd;
+use num::Integer;
 use std::cmp::Ordering;
 use std::str::FromStr;
 
@@ -30,124 +32,195 @@ pub fn input_generator(input: &str) -> Vec<Robot> {
     input.lines().map(|l| l.parse().unwrap()).collect()
 }
 
-fn check_grid_size(robots: &[Robot]) -> Vec2i {
-    let (min_x, max_x) = robots
-        .iter()
-        .map(|r| r.pos.x)
-        .minmax()
-        .into_option()
-        .unwrap();
-    let (min_y, max_y) = robots
-        .iter()
-        .map(|r| r.pos.y)
-        .minmax()
-        .into_option()
-        .unwrap();
-    assert_eq!(min_x, 0);
-    assert_eq!(min_y, 0);
-    assert_eq!((max_x + 1) % 2, 1);
-    assert_eq!((max_y + 1) % 2, 1);
-
-    Vec2i::new(max_x + 1, max_y + 1)
+/// The room the robots move in, they wrap around at its edges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Room {
+    pub size: Vec2i,
 }
 
-fn calc_safety_factor(robots: &[Robot], size: Vec2i) -> usize {
-    let half_x = size.x / 2;
-    let half_y = size.y / 2;
-    let (mut a, mut b, mut c, mut d) = (0, 0, 0, 0);
-    for r in robots {
-        match (r.pos.x.cmp(&half_x), r.pos.y.cmp(&half_y)) {
-            (Ordering::Less, Ordering::Less) => a += 1,
-            (Ordering::Greater, Ordering::Less) => b += 1,
-            (Ordering::Less, Ordering::Greater) => c += 1,
-            (Ordering::Greater, Ordering::Greater) => d += 1,
-            _ => {}
-        }
+impl Default for Room {
+    fn default() -> Self {
+        Self::new(101, 103)
     }
-
-    a * b * c * d
 }
 
-fn calc_next_pos(r: &Robot, amount: i64, size: Vec2i) -> Vec2i {
-    let mut next_pos = r.pos + amount * r.vel;
-    next_pos.x = next_pos.x.rem_euclid(size.x);
-    next_pos.y = next_pos.y.rem_euclid(size.y);
-    next_pos
+/// Mean and variance of the robot coordinates along one axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisStats {
+    pub mean: f64,
+    pub variance: f64,
 }
 
-#[aoc(day14, part1)]
-pub fn part1(input: &[Robot]) -> usize {
-    let mut robots = input.to_vec();
-    let size = check_grid_size(&robots);
-    for r in &mut robots {
-        r.pos = calc_next_pos(r, 100, size);
+impl Room {
+    pub fn new(size_x: i64, size_y: i64) -> Self {
+        Self {
+            size: Vec2i::new(size_x, size_y),
+        }
     }
 
-    calc_safety_factor(&robots, size)
-}
+    /// Position of the robot after `n` seconds.
+    pub fn pos_after(&self, r: &Robot, n: i64) -> Vec2i {
+        let mut next_pos = r.pos + n * r.vel;
+        next_pos.x = next_pos.x.rem_euclid(self.size.x);
+        next_pos.y = next_pos.y.rem_euclid(self.size.y);
+        next_pos
+    }
 
-#[aoc(day14, part2)]
-pub fn part2(input: &[Robot]) -> usize {
-    let mut robots = input.to_vec();
-    let size = check_grid_size(&robots);
-
-    // this is just guesswork
-    // calculate some measures about the position distribution
-    let mut entropies = vec![];
-    let mut means = vec![];
-    let mut devs = vec![];
-    for _ in 0..(size.x * size.y) {
-        for r in &mut robots {
-            r.pos = calc_next_pos(r, 1, size);
+    pub fn step(&self, robots: &mut [Robot], n: i64) {
+        for r in robots {
+            r.pos = self.pos_after(r, n);
+        }
+    }
+
+    /// Number of robots in the top left, top right, bottom left and bottom right quadrant.
+    ///
+    /// Robots exactly in the middle do not belong to any quadrant.
+    pub fn quadrant_counts(&self, robots: &[Robot]) -> [usize; 4] {
+        let half_x = self.size.x / 2;
+        let half_y = self.size.y / 2;
+        let mut counts = [0; 4];
+        for r in robots {
+            match (r.pos.x.cmp(&half_x), r.pos.y.cmp(&half_y)) {
+                (Ordering::Less, Ordering::Less) => counts[0] += 1,
+                (Ordering::Greater, Ordering::Less) => counts[1] += 1,
+                (Ordering::Less, Ordering::Greater) => counts[2] += 1,
+                (Ordering::Greater, Ordering::Greater) => counts[3] += 1,
+                _ => {}
+            }
         }
 
+        counts
+    }
+
+    pub fn safety_factor(&self, robots: &[Robot]) -> usize {
+        self.quadrant_counts(robots).iter().product()
+    }
+
+    /// Draws the number of robots on each tile, `.` for empty tiles.
+    pub fn render(&self, robots: &[Robot]) -> String {
         let counts = robots.iter().map(|r| r.pos).counts();
-        entropies.push(
-            -counts
-                .values()
-                .map(|count| {
-                    let p = *count as f64 / robots.len() as f64;
-                    p * p.log2()
-                })
-                .sum::<f64>(),
-        );
+        let mut s = String::new();
+        for y in 0..self.size.y {
+            for x in 0..self.size.x {
+                match counts.get(&Vec2i::new(x, y)) {
+                    Some(count) => s.push_str(&count.to_string()),
+                    None => s.push('.'),
+                }
+            }
+            s.push('\n');
+        }
+        s
+    }
 
-        let mean: Vec2f = robots
+    /// Statistics of the coordinates along `axis` (0 for x, 1 for y) after `t` seconds.
+    ///
+    /// These only depend on `t` modulo the size of the room along that axis.
+    pub fn axis_stats(&self, robots: &[Robot], axis: usize, t: i64) -> AxisStats {
+        let n = robots.len() as f64;
+        let (sum, sum_sq) = robots
             .iter()
-            .map(|r| Vec2f::new(r.pos.x as _, r.pos.y as _))
-            .sum::<Vec2f>()
-            / robots.len() as f64;
-        means.push(mean);
-        devs.push(
-            robots
-                .iter()
-                .map(|r| {
-                    let d = Vec2f::new(r.pos.x as _, r.pos.y as _) - mean;
-                    d.component_mul(&d)
-                })
-                .sum::<Vec2f>()
-                / robots.len() as f64,
-        );
+            .map(|r| (r.pos[axis] + t * r.vel[axis]).rem_euclid(self.size[axis]) as f64)
+            .fold((0.0, 0.0), |(sum, sum_sq), c| (sum + c, sum_sq + c * c));
+        let mean = sum / n;
+        AxisStats {
+            mean,
+            variance: sum_sq / n - mean * mean,
+        }
+    }
+
+    /// The time within one period of `axis` where the robots are the most clustered along it.
+    pub fn min_variance_time(&self, robots: &[Robot], axis: usize) -> i64 {
+        (0..self.size[axis])
+            .min_by(|&a, &b| {
+                self.axis_stats(robots, axis, a)
+                    .variance
+                    .total_cmp(&self.axis_stats(robots, axis, b).variance)
+            })
+            .unwrap()
+    }
+
+    /// Combines the least spread out time of each axis into the first time where both happen at once.
+    ///
+    /// Returns `None` if the room dimensions are not coprime.
+    pub fn clustered_time(&self, robots: &[Robot]) -> Option<i64> {
+        chinese_remainder(
+            self.min_variance_time(robots, 0),
+            self.size.x,
+            self.min_variance_time(robots, 1),
+            self.size.y,
+        )
+    }
+
+    /// Number of seconds after which all robots are back at their starting positions.
+    pub fn period(&self) -> i64 {
+        self.size.x.lcm(&self.size.y)
     }
 
-    // remember to add 1 when submitting
+    /// Searches all seconds from 1 up to and including one full period for the frame with the lowest score.
+    pub fn best_frame(&self, robots: &[Robot], score: impl Fn(&[Vec2i]) -> f64) -> i64 {
+        (1..=self.period())
+            .map(|t| {
+                let frame = robots.iter().map(|r| self.pos_after(r, t)).collect_vec();
+                (t, score(&frame))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap()
+            .0
+    }
+}
+
+/// Safety factor of the room after the robots moved for `n` seconds.
+pub fn safety_factor_after(room: &Room, robots: &[Robot], n: i64) -> usize {
+    let mut robots = robots.to_vec();
+    room.step(&mut robots, n);
+    room.safety_factor(&robots)
+}
+
+#[aoc(day14, part1)]
+pub fn part1(input: &[Robot]) -> usize {
+    safety_factor_after(&Room::default(), input, 100)
+}
+
+/// Sum of the variances of both axes, small when the positions are close together.
+pub fn spread(positions: &[Vec2i]) -> f64 {
+    let n = positions.len() as f64;
+    (0..2)
+        .map(|axis| {
+            let mean = positions.iter().map(|p| p[axis] as f64).sum::<f64>() / n;
+            positions
+                .iter()
+                .map(|p| (p[axis] as f64 - mean).powi(2))
+                .sum::<f64>()
+                / n
+        })
+        .sum()
+}
 
-    println!(
-        "entropies: {:?}",
-        entropies.iter().position_minmax().into_option().unwrap()
-    );
+/// Smallest non-negative `t` with `t = a1 mod m1` and `t = a2 mod m2` for coprime moduli.
+fn chinese_remainder(a1: i64, m1: i64, a2: i64, m2: i64) -> Option<i64> {
+    let ExtendedGcd { gcd, x, .. } = m1.extended_gcd(&m2);
+    if gcd != 1 {
+        return None;
+    }
 
-    println!(
-        "means: {:?}",
-        means.iter().position_minmax().into_option().unwrap()
-    );
+    // x is the inverse of m1 modulo m2
+    let k = ((a2 - a1) * x).rem_euclid(m2);
+    Some((a1 + k * m1).rem_euclid(m1 * m2))
+}
 
-    println!(
-        "devs: {:?}",
-        devs.iter().position_minmax().into_option().unwrap()
-    );
+/// First second at which the robots form a picture.
+pub fn picture_time(room: &Room, robots: &[Robot]) -> i64 {
+    match room.clustered_time(robots) {
+        // a picture at the start shows up again after one full period
+        Some(0) => room.period(),
+        Some(t) => t,
+        None => room.best_frame(robots, spread),
+    }
+}
 
-    0
+#[aoc(day14, part2)]
+pub fn part2(input: &[Robot]) -> i64 {
+    picture_time(&Room::default(), input)
 }
 
 #[cfg(test)]
@@ -170,6 +243,114 @@ p=9,5 v=-3,-3"#;
 
     #[test]
     fn test_part1() {
-        assert_eq!(part1(&input_generator(INPUT)), 12);
+        assert_eq!(
+            safety_factor_after(&Room::new(11, 7), &input_generator(INPUT), 100),
+            12
+        );
+    }
+
+    #[test]
+    fn test_room() {
+        let room = Room::new(11, 7);
+        let mut robots = input_generator(INPUT);
+        room.step(&mut robots, 100);
+        assert_eq!(
+            room.render(&robots),
+            r#"......2..1.
+...........
+1..........
+.11........
+.....1.....
+...12......
+.1....1....
+"#
+        );
+        assert_eq!(room.quadrant_counts(&robots), [1, 3, 4, 1]);
+
+        let robot: Robot = "p=2,4 v=2,-3".parse().unwrap();
+        assert_eq!(room.pos_after(&robot, 5), Vec2i::new(1, 3));
+    }
+
+    #[test]
+    fn test_axis_stats() {
+        let room = Room::new(11, 7);
+        let robots = input_generator(INPUT);
+        let stats = room.axis_stats(&robots, 0, 0);
+        assert_eq!(stats.mean, 58.0 / 12.0);
+        assert!((stats.variance - (422.0 / 12.0 - stats.mean * stats.mean)).abs() < 1e-9);
+        assert_eq!(
+            room.axis_stats(&robots, 1, 3),
+            room.axis_stats(&robots, 1, 10)
+        );
+        assert_eq!(
+            room.axis_stats(&robots, 0, 100),
+            room.axis_stats(&robots, 0, 1)
+        );
+    }
+
+    #[test]
+    fn test_chinese_remainder() {
+        assert_eq!(chinese_remainder(2, 101, 5, 103), Some(5052));
+        assert_eq!(chinese_remainder(0, 11, 0, 7), Some(0));
+        assert_eq!(chinese_remainder(3, 11, 4, 7), Some(25));
+        assert_eq!(chinese_remainder(1, 4, 1, 6), None);
+    }
+
+    /// Robots that draw a filled triangle with its tip at `(40, 30)` after `t` seconds, mixed with random noise.
+    fn synthetic_picture(room: &Room, t: i64) -> Vec<Robot> {
+        let mut seed = 0x14u64;
+        let mut random = |m: i64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as i64 % m
+        };
+        let mut robots = vec![];
+        for dy in 0..15 {
+            for dx in -dy..=dy {
+                let vel = Vec2i::new(random(201) - 100, random(201) - 100);
+                let pos = Vec2i::new(40 + dx, 30 + dy) - t * vel;
+                robots.push(Robot {
+                    pos: Vec2i::new(pos.x.rem_euclid(room.size.x), pos.y.rem_euclid(room.size.y)),
+                    vel,
+                });
+            }
+        }
+        for _ in 0..150 {
+            robots.push(Robot {
+                pos: Vec2i::new(random(room.size.x), random(room.size.y)),
+                vel: Vec2i::new(random(201) - 100, random(201) - 100),
+            });
+        }
+        robots
+    }
+
+    #[test]
+    fn test_part2_synthetic() {
+        let room = Room::default();
+        let robots = synthetic_picture(&room, 6876);
+        assert_eq!(picture_time(&room, &robots), 6876);
+        assert_eq!(room.best_frame(&robots, spread), 6876);
+
+        let mut frame = robots.clone();
+        room.step(&mut frame, 6876);
+        let rendered = room.render(&frame);
+        let base = rendered.lines().nth(44).unwrap();
+        assert!(!base[26..=54].contains('.'));
+    }
+
+    #[test]
+    fn test_part2_picture_at_start() {
+        let room = Room::default();
+        let robots = synthetic_picture(&room, 0);
+        assert_eq!(picture_time(&room, &robots), room.period());
+    }
+
+    #[test]
+    fn test_part2_non_coprime_room() {
+        let room = Room::new(90, 60);
+        let robots = synthetic_picture(&room, 137);
+        assert_eq!(room.clustered_time(&robots), None);
+        assert_eq!(picture_time(&room, &robots), 137);
     }
 }
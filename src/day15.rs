@@ -3,6 +3,8 @@ use aoc_runner_derive::{aoc, aoc_generator};
 use itertools::Itertools;
 use rustc_hash::FxHashSet;
 use std::cmp::Reverse;
+use std::fmt::{Display, Formatter};
+use thiserror::Error;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Tile {
@@ -23,6 +25,8 @@ impl TryFrom<char> for Tile {
             '#' => Tile::Wall,
             'O' => Tile::Box,
             '@' => Tile::Robot,
+            '[' => Tile::BoxL,
+            ']' => Tile::BoxR,
             _ => {
                 return Err(());
             }
@@ -34,7 +38,7 @@ impl TryFrom<char> for Tile {
 pub fn input_generator(input: &str) -> (Grid<Tile>, Vec<Direction>) {
     let (grid, directions) = input.split_once("\n\n").unwrap();
     (
-        grid.parse().unwrap(),
+        parse_map(grid).unwrap(),
         directions
             .chars()
             .filter_map(|c| Direction::try_from(c).ok())
@@ -42,82 +46,334 @@ pub fn input_generator(input: &str) -> (Grid<Tile>, Vec<Direction>) {
     )
 }
 
-fn run(grid: &mut Grid<Tile>, directions: &[Direction]) -> i64 {
-    let (mut pos, _) = grid
-        .pos_iter()
-        .find(|(_, &tile)| tile == Tile::Robot)
-        .unwrap();
-    'outer: for d in directions {
+impl From<Tile> for char {
+    fn from(value: Tile) -> Self {
+        match value {
+            Tile::Empty => '.',
+            Tile::Wall => '#',
+            Tile::BoxL => '[',
+            Tile::BoxR => ']',
+            Tile::Box => 'O',
+            Tile::Robot => '@',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum WarehouseError {
+    #[error("invalid character {c:?} at {pos:?}")]
+    InvalidChar { pos: Vec2i, c: char },
+    #[error("line {line} has a different length than the first line")]
+    Ragged { line: usize },
+    #[error("empty map")]
+    Empty,
+    #[error("no robot")]
+    NoRobot,
+    #[error("multiple robots at {0:?} and {1:?}")]
+    MultipleRobots(Vec2i, Vec2i),
+    #[error("border is not a wall at {0:?}")]
+    OpenBorder(Vec2i),
+    #[error("unmatched box half at {0:?}")]
+    UnmatchedBoxHalf(Vec2i),
+}
+
+/// Parses a warehouse map, reporting the position of the first character that is not a tile.
+pub fn parse_map(s: &str) -> Result<Grid<Tile>, WarehouseError> {
+    let lines = s
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect_vec();
+    let Some(first) = lines.first() else {
+        return Err(WarehouseError::Empty);
+    };
+    for (y, l) in lines.iter().enumerate() {
+        if l.len() != first.len() {
+            return Err(WarehouseError::Ragged { line: y });
+        }
+        if let Some((x, c)) = l
+            .chars()
+            .enumerate()
+            .find(|&(_, c)| Tile::try_from(c).is_err())
+        {
+            return Err(WarehouseError::InvalidChar {
+                pos: Vec2i::new(x as i64, y as i64),
+                c,
+            });
+        }
+    }
+
+    Ok(s.parse().unwrap())
+}
+
+/// Checks that the map has exactly one robot, is enclosed by walls and every `[` is directly followed by a `]`.
+///
+/// Returns the position of the robot.
+pub fn validate(grid: &Grid<Tile>) -> Result<Vec2i, WarehouseError> {
+    let mut robot = None;
+    for (pos, &tile) in grid.pos_iter() {
+        let on_border = pos.x == 0
+            || pos.y == 0
+            || pos.x as usize + 1 == grid.size_x
+            || pos.y as usize + 1 == grid.size_y;
+        if on_border && tile != Tile::Wall {
+            return Err(WarehouseError::OpenBorder(pos));
+        }
+
+        match tile {
+            Tile::Robot => {
+                if let Some(other) = robot {
+                    return Err(WarehouseError::MultipleRobots(other, pos));
+                }
+                robot = Some(pos);
+            }
+            Tile::BoxL => {
+                let right = Direction::East.offset(&pos);
+                if !grid.in_bounds(&right) || grid[right] != Tile::BoxR {
+                    return Err(WarehouseError::UnmatchedBoxHalf(pos));
+                }
+            }
+            Tile::BoxR => {
+                let left = Direction::West.offset(&pos);
+                if !grid.in_bounds(&left) || grid[left] != Tile::BoxL {
+                    return Err(WarehouseError::UnmatchedBoxHalf(pos));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    robot.ok_or(WarehouseError::NoRobot)
+}
+
+/// Contents of a warehouse cell, boxes may span several cells that all carry the same id.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Cell {
+    Empty,
+    Wall,
+    Box(usize),
+    Robot,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MoveOutcome {
+    Moved { boxes_pushed: usize },
+    Blocked,
+}
+
+/// A warehouse that is simulated one robot move at a time.
+#[derive(Debug, Clone)]
+pub struct Warehouse {
+    grid: Grid<Cell>,
+    robot: Vec2i,
+    /// cells covered by each box
+    boxes: Vec<Vec<Vec2i>>,
+    /// every step with the cells and boxes that were moved, cells in the order they were moved
+    history: Vec<(Direction, Vec<Vec2i>, Vec<usize>)>,
+}
+
+impl Warehouse {
+    /// Builds a warehouse from a parsed map, `[]` pairs become boxes of width 2.
+    pub fn new(grid: Grid<Tile>) -> Result<Self, WarehouseError> {
+        Self::widened(&grid, 1)
+    }
+
+    /// Builds a warehouse where every tile of the map is `factor` cells wide.
+    pub fn widened(grid: &Grid<Tile>, factor: usize) -> Result<Self, WarehouseError> {
+        assert!(factor > 0);
+        let robot = validate(grid)?;
+        let mut warehouse = Self {
+            grid: Grid::new_from_element(factor * grid.size_x, grid.size_y, Cell::Empty),
+            robot: Vec2i::new(factor as i64 * robot.x, robot.y),
+            boxes: vec![],
+            history: vec![],
+        };
+        let factor = factor as i64;
+        for (pos, &tile) in grid.pos_iter() {
+            let cells = (0..factor).map(|i| Vec2i::new(factor * pos.x + i, pos.y));
+            match tile {
+                Tile::Empty | Tile::BoxR | Tile::Robot => {}
+                Tile::Wall => cells.for_each(|p| warehouse.grid[p] = Cell::Wall),
+                Tile::Box => {
+                    warehouse.add_box(cells);
+                }
+                Tile::BoxL => {
+                    let right = Direction::East.offset(&pos);
+                    warehouse.add_box(
+                        cells.chain((0..factor).map(|i| Vec2i::new(factor * right.x + i, pos.y))),
+                    );
+                }
+            }
+        }
+        warehouse.grid[warehouse.robot] = Cell::Robot;
+        Ok(warehouse)
+    }
+
+    /// Places a box covering the given cells and returns its id, all cells have to be empty.
+    pub fn add_box(&mut self, cells: impl IntoIterator<Item = Vec2i>) -> Option<usize> {
+        let cells = cells.into_iter().collect_vec();
+        if cells.is_empty()
+            || !cells
+                .iter()
+                .all(|p| self.grid.in_bounds(p) && self.grid[*p] == Cell::Empty)
+        {
+            return None;
+        }
+
+        let id = self.boxes.len();
+        for &p in &cells {
+            self.grid[p] = Cell::Box(id);
+        }
+        self.boxes.push(cells);
+        Some(id)
+    }
+
+    pub fn grid(&self) -> &Grid<Cell> {
+        &self.grid
+    }
+
+    pub fn robot(&self) -> Vec2i {
+        self.robot
+    }
+
+    pub fn boxes(&self) -> &[Vec<Vec2i>] {
+        &self.boxes
+    }
+
+    pub fn steps(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn step(&mut self, d: Direction) -> MoveOutcome {
+        let grid = &mut self.grid;
         let mut to_move = FxHashSet::default();
-        let mut q = vec![pos];
+        let mut boxes = vec![];
+        let mut q = vec![self.robot];
         while let Some(p) = q.pop() {
             if to_move.contains(&p) {
                 continue;
             }
 
             match grid[p] {
-                Tile::Robot | Tile::Box => {
+                Cell::Robot => {
                     to_move.insert(p);
                     q.push(d.offset(&p));
                 }
-                Tile::BoxL => {
-                    let neighbor = Direction::East.offset(&p);
-                    to_move.insert(p);
-                    to_move.insert(neighbor);
-                    q.push(d.offset(&p));
-                    q.push(d.offset(&neighbor));
+                Cell::Box(id) => {
+                    boxes.push(id);
+                    for cell in &self.boxes[id] {
+                        to_move.insert(*cell);
+                        q.push(d.offset(cell));
+                    }
                 }
-                Tile::BoxR => {
-                    let neighbor = Direction::West.offset(&p);
-                    to_move.insert(p);
-                    to_move.insert(neighbor);
-                    q.push(d.offset(&p));
-                    q.push(d.offset(&neighbor));
+                Cell::Empty => continue,
+                Cell::Wall => {
+                    self.history.push((d, vec![], vec![]));
+                    return MoveOutcome::Blocked;
                 }
-                Tile::Empty => continue,
-                Tile::Wall => continue 'outer,
             }
         }
 
-        to_move
+        let moved = to_move
             .into_iter()
             .sorted_unstable_by_key(|p| Reverse(d.vec().dot(p)))
-            .for_each(|p| {
+            .collect_vec();
+        for &p in &moved {
+            let neighbor = d.offset(&p);
+            grid[neighbor] = grid[p];
+            grid[p] = Cell::Empty;
+        }
+        for &id in &boxes {
+            self.boxes[id].iter_mut().for_each(|p| *p = d.offset(p));
+        }
+        self.robot = d.offset(&self.robot);
+        let boxes_pushed = boxes.len();
+        self.history.push((d, moved, boxes));
+        MoveOutcome::Moved { boxes_pushed }
+    }
+
+    pub fn run(&mut self, directions: &[Direction]) {
+        for &d in directions {
+            self.step(d);
+        }
+    }
+
+    /// Reverts the last step and returns its direction.
+    pub fn undo(&mut self) -> Option<Direction> {
+        let (d, moved, boxes) = self.history.pop()?;
+        if !moved.is_empty() {
+            for p in moved.into_iter().rev() {
                 let neighbor = d.offset(&p);
-                grid[neighbor] = grid[p];
-                grid[p] = Tile::Empty;
-            });
-        pos = d.offset(&pos);
+                self.grid[p] = self.grid[neighbor];
+                self.grid[neighbor] = Cell::Empty;
+            }
+            for id in boxes {
+                self.boxes[id]
+                    .iter_mut()
+                    .for_each(|p| *p = d.opposite().offset(p));
+            }
+            self.robot = d.opposite().offset(&self.robot);
+        }
+
+        Some(d)
     }
 
-    grid.pos_iter()
-        .filter(|(_, &tile)| matches!(tile, Tile::Box | Tile::BoxL))
-        .map(|(pos, _)| 100 * pos.y + pos.x)
-        .sum()
+    /// Sum of `100 * y + x` over the top left corner of every box.
+    pub fn gps_sum(&self) -> i64 {
+        self.boxes
+            .iter()
+            .map(|cells| {
+                let y = cells.iter().map(|p| p.y).min().unwrap();
+                let x = cells.iter().map(|p| p.x).min().unwrap();
+                100 * y + x
+            })
+            .sum()
+    }
+}
+
+impl Display for Warehouse {
+    /// Boxes are drawn row by row as `O` if one cell wide and `[==]` otherwise.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (pos, cell) in self.grid.pos_iter() {
+            let c = match cell {
+                Cell::Empty => '.',
+                Cell::Wall => '#',
+                Cell::Robot => '@',
+                Cell::Box(_) => {
+                    let same_box = |p: Vec2i| self.grid.in_bounds(&p) && self.grid[p] == *cell;
+                    match (
+                        same_box(Direction::West.offset(&pos)),
+                        same_box(Direction::East.offset(&pos)),
+                    ) {
+                        (false, false) => 'O',
+                        (false, true) => '[',
+                        (true, false) => ']',
+                        (true, true) => '=',
+                    }
+                }
+            };
+            write!(f, "{c}")?;
+            if pos.x as usize + 1 == self.grid.size_x {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn run(warehouse: &mut Warehouse, directions: &[Direction]) -> i64 {
+    warehouse.run(directions);
+    warehouse.gps_sum()
 }
 
 #[aoc(day15, part1)]
 pub fn part1((grid, directions): &(Grid<Tile>, Vec<Direction>)) -> i64 {
-    let mut grid = grid.clone();
-    run(&mut grid, directions)
+    run(&mut Warehouse::new(grid.clone()).unwrap(), directions)
 }
 
 #[aoc(day15, part2)]
-pub fn part2((input_grid, directions): &(Grid<Tile>, Vec<Direction>)) -> i64 {
-    let mut grid = Grid::new_from_element(2 * input_grid.size_x, input_grid.size_x, Tile::Empty);
-    input_grid.pos_iter().for_each(|(pos, &tile)| {
-        let (t1, t2) = match tile {
-            Tile::Empty => (Tile::Empty, Tile::Empty),
-            Tile::Wall => (Tile::Wall, Tile::Wall),
-            Tile::Box => (Tile::BoxL, Tile::BoxR),
-            Tile::Robot => (Tile::Robot, Tile::Empty),
-            _ => unreachable!(),
-        };
-        grid[Vec2i::new(2 * pos.x, pos.y)] = t1;
-        grid[Vec2i::new(2 * pos.x + 1, pos.y)] = t2;
-    });
-
-    run(&mut grid, directions)
+pub fn part2((grid, directions): &(Grid<Tile>, Vec<Direction>)) -> i64 {
+    run(&mut Warehouse::widened(grid, 2).unwrap(), directions)
 }
 
 #[cfg(test)]
@@ -171,4 +427,215 @@ v^^>>><<^^<>>^v^<v^vv<>v^<<>^<^v^v><^<<<><<^<v><v<>vv>>v><v^<vv<>v^<<^"#;
     fn test_part2_1() {
         assert_eq!(part2(&input_generator(INPUT_1)), 9021);
     }
+
+    #[test]
+    fn test_warehouse_steps() {
+        let (grid, directions) = input_generator(INPUT_2);
+        let mut warehouse = Warehouse::new(grid).unwrap();
+        let start = warehouse.to_string();
+        let outcomes = directions.iter().map(|&d| warehouse.step(d)).collect_vec();
+        assert_eq!(
+            outcomes[..6],
+            [
+                MoveOutcome::Blocked,
+                MoveOutcome::Moved { boxes_pushed: 0 },
+                MoveOutcome::Blocked,
+                MoveOutcome::Moved { boxes_pushed: 1 },
+                MoveOutcome::Moved { boxes_pushed: 2 },
+                MoveOutcome::Blocked,
+            ]
+        );
+        assert_eq!(
+            warehouse.to_string(),
+            r#"########
+#....OO#
+##.....#
+#.....O#
+#.#O@..#
+#...O..#
+#...O..#
+########
+"#
+        );
+        assert_eq!(warehouse.gps_sum(), 2028);
+        assert_eq!(warehouse.robot(), Vec2i::new(4, 4));
+
+        while warehouse.undo().is_some() {}
+        assert_eq!(warehouse.steps(), 0);
+        assert_eq!(warehouse.to_string(), start);
+    }
+
+    #[test]
+    fn test_warehouse_wide_push() {
+        let (grid, _) = input_generator(INPUT_1);
+        let mut warehouse = Warehouse::widened(&grid, 2).unwrap();
+        let before = warehouse.clone();
+        assert_eq!(
+            warehouse.step(Direction::West),
+            MoveOutcome::Moved { boxes_pushed: 1 }
+        );
+        assert_eq!(
+            warehouse.step(Direction::South),
+            MoveOutcome::Moved { boxes_pushed: 0 }
+        );
+        assert_eq!(warehouse.undo(), Some(Direction::South));
+        assert_eq!(warehouse.undo(), Some(Direction::West));
+        assert_eq!(warehouse.grid(), before.grid());
+        assert_eq!(warehouse.undo(), None);
+    }
+
+    #[test]
+    fn test_warehouse_widening_factor() {
+        let (grid, directions) = input_generator(INPUT_2);
+        let mut warehouse = Warehouse::widened(&grid, 3).unwrap();
+        assert_eq!(warehouse.grid().size_x, 24);
+        assert_eq!(warehouse.grid().size_y, 8);
+        assert!(warehouse.boxes().iter().all(|cells| cells.len() == 3));
+        assert_eq!(
+            warehouse.to_string().lines().nth(1),
+            Some("###......[=]...[=]...###")
+        );
+        warehouse.run(&directions);
+        assert_eq!(
+            warehouse.gps_sum(),
+            warehouse
+                .boxes()
+                .iter()
+                .map(|cells| 100 * cells[0].y + cells[0].x)
+                .sum::<i64>()
+        );
+        while warehouse.undo().is_some() {}
+        assert_eq!(
+            warehouse.grid(),
+            Warehouse::widened(&grid, 3).unwrap().grid()
+        );
+    }
+
+    #[test]
+    fn test_warehouse_tall_box() {
+        let grid: Grid<Tile> = "#######\n#.....#\n#.....#\n#.....#\n#..@..#\n#######"
+            .parse()
+            .unwrap();
+        let mut warehouse = Warehouse::new(grid).unwrap();
+        let tall = warehouse
+            .add_box([Vec2i::new(4, 1), Vec2i::new(4, 2)])
+            .unwrap();
+        let bent = warehouse
+            .add_box([Vec2i::new(2, 3), Vec2i::new(3, 3), Vec2i::new(2, 2)])
+            .unwrap();
+        assert_eq!(warehouse.add_box([Vec2i::new(3, 3)]), None);
+        let start = warehouse.clone();
+
+        use Direction::*;
+        let outcomes = [North, North, West, West, North, East].map(|d| warehouse.step(d));
+        assert_eq!(
+            outcomes,
+            [
+                MoveOutcome::Moved { boxes_pushed: 1 },
+                MoveOutcome::Blocked,
+                MoveOutcome::Moved { boxes_pushed: 0 },
+                MoveOutcome::Moved { boxes_pushed: 0 },
+                MoveOutcome::Moved { boxes_pushed: 0 },
+                MoveOutcome::Moved { boxes_pushed: 2 },
+            ]
+        );
+        assert_eq!(
+            warehouse.to_string(),
+            "#######\n#..O.O#\n#.@[]O#\n#.....#\n#.....#\n#######\n"
+        );
+        assert_eq!(
+            warehouse.boxes()[tall],
+            [Vec2i::new(5, 1), Vec2i::new(5, 2)]
+        );
+        assert_eq!(
+            warehouse.boxes()[bent],
+            [Vec2i::new(3, 2), Vec2i::new(4, 2), Vec2i::new(3, 1)]
+        );
+        assert_eq!(warehouse.gps_sum(), 105 + 103);
+
+        while warehouse.undo().is_some() {}
+        assert_eq!(warehouse.grid(), start.grid());
+        assert_eq!(warehouse.boxes(), start.boxes());
+    }
+
+    const INPUT_TALL: &str = r#"#######
+#.....#
+#..O..#
+#..@..#
+#.....#
+#..O..#
+#..O..#
+#.....#
+#######
+
+vv^^^"#;
+    const INPUT_WIDE: &str = r#"##########
+#..O.O@..#
+#........#
+##########
+
+<<<"#;
+
+    #[test]
+    fn test_part1_non_square() {
+        assert_eq!(part1(&input_generator(INPUT_TALL)), 1409);
+        assert_eq!(part1(&input_generator(INPUT_WIDE)), 203);
+    }
+
+    #[test]
+    fn test_part2_non_square() {
+        assert_eq!(part2(&input_generator(INPUT_TALL)), 1418);
+        assert_eq!(part2(&input_generator(INPUT_WIDE)), 212);
+    }
+
+    #[test]
+    fn test_part2_matches_widened_map() {
+        let (grid, directions) = input_generator(INPUT_TALL);
+        let widened = Warehouse::widened(&grid, 2).unwrap();
+        assert_eq!(widened.grid().size_x, 14);
+        assert_eq!(widened.grid().size_y, 9);
+        let (wide_grid, _) = input_generator(&format!("{widened}\n\n"));
+        assert_eq!(
+            part1(&(wide_grid, directions.clone())),
+            part2(&(grid, directions))
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        let check = |map: &str| Warehouse::new(parse_map(map)?);
+        assert_eq!(
+            parse_map("####\n#@x#\n####").unwrap_err(),
+            WarehouseError::InvalidChar {
+                pos: Vec2i::new(2, 1),
+                c: 'x'
+            }
+        );
+        assert_eq!(
+            parse_map("####\n#@.#\n###").unwrap_err(),
+            WarehouseError::Ragged { line: 2 }
+        );
+        assert_eq!(parse_map("\n").unwrap_err(), WarehouseError::Empty);
+        assert_eq!(
+            check("####\n#..#\n####").unwrap_err(),
+            WarehouseError::NoRobot
+        );
+        assert_eq!(
+            check("#####\n#@.@#\n#####").unwrap_err(),
+            WarehouseError::MultipleRobots(Vec2i::new(1, 1), Vec2i::new(3, 1))
+        );
+        assert_eq!(
+            check("#####\n#@...\n#####").unwrap_err(),
+            WarehouseError::OpenBorder(Vec2i::new(4, 1))
+        );
+        assert_eq!(
+            check("#####\n#@[.#\n#####").unwrap_err(),
+            WarehouseError::UnmatchedBoxHalf(Vec2i::new(2, 1))
+        );
+        assert_eq!(
+            check("#####\n#@][#\n#####").unwrap_err(),
+            WarehouseError::UnmatchedBoxHalf(Vec2i::new(2, 1))
+        );
+        assert!(check("######\n#@[]O#\n######").is_ok());
+    }
 }
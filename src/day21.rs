@@ -1,9 +1,11 @@
+use crate::common::{Direction, Grid, Vec2i};
 use aoc_runner_derive::{aoc, aoc_generator};
 use itertools::Itertools;
-use rustc_hash::{FxHashMap, FxHashSet};
-use std::cell::RefCell;
+use num::traits::{CheckedAdd, CheckedMul, FromPrimitive, Zero};
+use rustc_hash::FxHashMap;
 use std::collections::VecDeque;
-use tinyvec::array_vec;
+use std::str::FromStr;
+use thiserror::Error;
 
 #[aoc_generator(day21)]
 pub fn input_generator(input: &str) -> Vec<String> {
@@ -15,231 +17,364 @@ pub fn input_generator(input: &str) -> Vec<String> {
         .collect()
 }
 
-fn dpad_neighbor(c: u8, dir: u8) -> Option<u8> {
-    match c {
-        b'^' => match dir {
-            b'v' => Some(b'v'),
-            b'>' => Some(b'A'),
-            _ => None,
-        },
-        b'v' => match dir {
-            b'^' => Some(b'^'),
-            b'>' => Some(b'>'),
-            b'<' => Some(b'<'),
-            _ => None,
-        },
-        b'<' => match dir {
-            b'>' => Some(b'v'),
-            _ => None,
-        },
-        b'>' => match dir {
-            b'^' => Some(b'A'),
-            b'<' => Some(b'v'),
-            _ => None,
-        },
-        b'A' => match dir {
-            b'v' => Some(b'>'),
-            b'<' => Some(b'^'),
-            _ => None,
-        },
-        _ => None,
-    }
+const NUMERIC_KEYPAD: &str = "\
++---+---+---+
+| 7 | 8 | 9 |
++---+---+---+
+| 4 | 5 | 6 |
++---+---+---+
+| 1 | 2 | 3 |
++---+---+---+
+    | 0 | A |
+    +---+---+";
+
+const DIRECTIONAL_KEYPAD: &str = "\
+    +---+---+
+    | ^ | A |
++---+---+---+
+| < | v | > |
++---+---+---+";
+
+const DIRECTIONS: [u8; 4] = [b'^', b'v', b'>', b'<'];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Keypad {
+    grid: Grid<Option<u8>>,
+    positions: FxHashMap<u8, Vec2i>,
 }
 
-fn numpad_neighbor(c: u8, dir: u8) -> Option<u8> {
-    match c {
-        b'0' => match dir {
-            b'^' => Some(b'2'),
-            b'>' => Some(b'A'),
-            _ => None,
-        },
-        b'1' => match dir {
-            b'^' => Some(b'4'),
-            b'>' => Some(b'2'),
-            _ => None,
-        },
-        b'2' => match dir {
-            b'^' => Some(b'5'),
-            b'v' => Some(b'0'),
-            b'>' => Some(b'3'),
-            b'<' => Some(b'1'),
-            _ => None,
-        },
-        b'3' => match dir {
-            b'^' => Some(b'6'),
-            b'v' => Some(b'A'),
-            b'<' => Some(b'2'),
-            _ => None,
-        },
-        b'4' => match dir {
-            b'^' => Some(b'7'),
-            b'v' => Some(b'1'),
-            b'>' => Some(b'5'),
-            _ => None,
-        },
-        b'5' => match dir {
-            b'^' => Some(b'8'),
-            b'v' => Some(b'2'),
-            b'>' => Some(b'6'),
-            b'<' => Some(b'4'),
-            _ => None,
-        },
-        b'6' => match dir {
-            b'^' => Some(b'9'),
-            b'v' => Some(b'3'),
-            b'<' => Some(b'5'),
-            _ => None,
-        },
-        b'7' => match dir {
-            b'v' => Some(b'4'),
-            b'>' => Some(b'8'),
-            _ => None,
-        },
-        b'8' => match dir {
-            b'v' => Some(b'5'),
-            b'>' => Some(b'9'),
-            b'<' => Some(b'7'),
-            _ => None,
-        },
-        b'9' => match dir {
-            b'v' => Some(b'6'),
-            b'<' => Some(b'8'),
-            _ => None,
-        },
-        b'A' => match dir {
-            b'^' => Some(b'3'),
-            b'<' => Some(b'0'),
-            _ => None,
-        },
-        _ => None,
+impl FromStr for Keypad {
+    type Err = ();
+
+    /// Parses a drawing like the puzzle's, where each key is a `| k |` cell and blank cells are gaps.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows = s
+            .lines()
+            .map(str::trim_end)
+            .filter(|l| l.contains('|'))
+            .map(|l| {
+                let l = l.as_bytes();
+                (0..l.len() / 4)
+                    .map(|x| match l[4 * x + 2] {
+                        b' ' => Ok(None),
+                        b'|' | b'+' | b'-' => Err(()),
+                        k if k.is_ascii_graphic() => Ok(Some(k)),
+                        _ => Err(()),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let size_x = rows.iter().map(Vec::len).max().ok_or(())?;
+
+        let mut grid = Grid::new_from_element(size_x, rows.len(), None);
+        let mut positions = FxHashMap::default();
+        for (y, row) in rows.into_iter().enumerate() {
+            for (x, key) in row.into_iter().enumerate() {
+                let pos = Vec2i::new(x as i64, y as i64);
+                if let Some(key) = key {
+                    if positions.insert(key, pos).is_some() {
+                        return Err(());
+                    }
+                }
+                grid[pos] = key;
+            }
+        }
+
+        Ok(Self { grid, positions })
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
-struct State<const N: usize> {
-    dpad: [u8; N],
-    numpad: u8,
-}
+impl Keypad {
+    pub fn numeric() -> Self {
+        NUMERIC_KEYPAD.parse().unwrap()
+    }
+
+    pub fn directional() -> Self {
+        DIRECTIONAL_KEYPAD.parse().unwrap()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = u8> + '_ {
+        self.grid.iter().flatten().copied()
+    }
 
-impl<const N: usize> Default for State<N> {
-    fn default() -> Self {
-        Self {
-            dpad: [b'A'; N],
-            numpad: b'A',
+    pub fn has_key(&self, key: u8) -> bool {
+        self.positions.contains_key(&key)
+    }
+
+    pub fn key_at(&self, pos: &Vec2i) -> Option<u8> {
+        if self.grid.in_bounds(pos) {
+            self.grid[*pos]
+        } else {
+            None
         }
     }
+
+    pub fn neighbor(&self, key: u8, dir: u8) -> Option<u8> {
+        let dir = Direction::try_from(dir as char).ok()?;
+        self.key_at(&dir.offset(self.positions.get(&key)?))
+    }
+
+    /// All shortest sequences of directions moving from one key to another without crossing a gap.
+    pub fn shortest_paths(&self, from: u8, to: u8) -> Vec<Vec<u8>> {
+        let mut distance = FxHashMap::default();
+        let mut q: VecDeque<_> = [(to, 0)].into();
+        while let Some((cur, d)) = q.pop_front() {
+            if distance.contains_key(&cur) {
+                continue;
+            }
+
+            distance.insert(cur, d);
+            for dir in DIRECTIONS {
+                if let Some(next) = self.neighbor(cur, dir) {
+                    q.push_back((next, d + 1));
+                }
+            }
+        }
+        if !distance.contains_key(&from) {
+            return vec![];
+        }
+
+        let mut paths = vec![];
+        let mut stack = vec![(from, vec![])];
+        while let Some((cur, path)) = stack.pop() {
+            if cur == to {
+                paths.push(path);
+                continue;
+            }
+
+            for dir in DIRECTIONS {
+                if let Some(next) = self.neighbor(cur, dir) {
+                    if distance
+                        .get(&next)
+                        .is_some_and(|&d| d + 1 == distance[&cur])
+                    {
+                        let mut path = path.clone();
+                        path.push(dir);
+                        stack.push((next, path));
+                    }
+                }
+            }
+        }
+
+        paths
+    }
 }
 
-#[derive(Default)]
-struct CachingSolver<const N: usize> {
-    cache: RefCell<FxHashMap<(u8, u8, usize), usize>>,
+/// Numeric types usable as press counts, arithmetic is checked so overflow yields `None`.
+pub trait Cost: Clone + Ord + Zero + CheckedAdd + CheckedMul + FromPrimitive {}
+
+impl<T: Clone + Ord + Zero + CheckedAdd + CheckedMul + FromPrimitive> Cost for T {}
+
+/// Computes the minimal number of button presses for a stack of keypads.
+///
+/// Every keypad is pressed by a robot whose arm is moved with the keypad below it, so all keypads
+/// but the last have to be directional. The robot at the first keypad is controlled by hand.
+///
+/// The cost tables are built bottom-up, one per keypad: the cost of moving between two keys is the
+/// (min, +) combination of the candidate paths with the table of the keypad below, so the work is
+/// linear in the number of keypads. `None` marks costs that overflowed `C`.
+pub struct KeypadSolver<C = usize> {
+    keypads: Vec<Keypad>,
+    tables: Vec<FxHashMap<(u8, u8), BestPath<C>>>,
 }
 
-impl<const N: usize> CachingSolver<N> {
-    fn solve_codes<S>(&self, codes: &[S]) -> usize
+/// Cost of a move together with the presses on the level below that achieve it.
+type BestPath<C> = (Option<C>, Vec<u8>);
+
+impl<C: Cost> KeypadSolver<C> {
+    pub fn new(keypads: Vec<Keypad>) -> Self {
+        assert!(!keypads.is_empty());
+        assert!(keypads[..keypads.len() - 1]
+            .iter()
+            .all(|k| DIRECTIONS.into_iter().chain([b'A']).all(|d| k.has_key(d))));
+
+        let mut tables: Vec<FxHashMap<_, _>> = Vec::with_capacity(keypads.len());
+        let mut candidates = FxHashMap::default();
+        for (layer, keypad) in keypads.iter().enumerate() {
+            if layer == 0 || keypads[layer - 1] != *keypad {
+                candidates = keypad
+                    .keys()
+                    .cartesian_product(keypad.keys().collect_vec())
+                    .map(|(from, to)| {
+                        let mut paths = keypad.shortest_paths(from, to);
+                        paths.iter_mut().for_each(|p| p.push(b'A'));
+                        ((from, to), paths)
+                    })
+                    .collect();
+            }
+
+            let table = candidates
+                .iter()
+                .filter_map(|(&pair, paths)| {
+                    paths
+                        .iter()
+                        .map(|path| {
+                            let cost = match tables.last() {
+                                None => C::from_usize(path.len()),
+                                Some(lower) => Self::sequence_cost(lower, path),
+                            };
+                            (cost, path)
+                        })
+                        .min_by(|(a, _), (b, _)| match (a, b) {
+                            (Some(a), Some(b)) => a.cmp(b),
+                            _ => b.is_some().cmp(&a.is_some()),
+                        })
+                        .map(|(cost, path)| (pair, (cost, path.clone())))
+                })
+                .collect();
+            tables.push(table);
+        }
+
+        Self { keypads, tables }
+    }
+
+    pub fn with_robots(robots: usize) -> Self {
+        let mut keypads = vec![Keypad::directional(); robots];
+        keypads.push(Keypad::numeric());
+        Self::new(keypads)
+    }
+
+    fn sequence_cost(table: &FxHashMap<(u8, u8), BestPath<C>>, keys: &[u8]) -> Option<C> {
+        let mut total_cost = C::zero();
+        let mut from = b'A';
+        for &to in keys {
+            total_cost = total_cost.checked_add(table[&(from, to)].0.as_ref()?)?;
+            from = to;
+        }
+
+        Some(total_cost)
+    }
+
+    pub fn solve_codes<S>(&self, codes: &[S]) -> Option<C>
     where
         S: AsRef<str>,
     {
-        let mut result = 0;
+        let mut result = C::zero();
         for code in codes {
             let code = code.as_ref();
-            let cost = self.solve_code(code);
-            let n: usize = code.trim_end_matches('A').parse().unwrap();
-            result += n * cost;
+            let cost = self.solve_code(code)?;
+            let n = C::from_usize(code.trim_end_matches('A').parse().unwrap())?;
+            result = result.checked_add(&n.checked_mul(&cost)?)?;
         }
 
-        result
+        Some(result)
     }
 
-    fn solve_code(&self, code: &str) -> usize {
-        self.solve_code_impl(code.as_bytes(), N)
+    pub fn solve_code(&self, code: &str) -> Option<C> {
+        Self::sequence_cost(self.tables.last().unwrap(), code.as_bytes())
     }
 
-    fn solve_code_impl(&self, code: &[u8], layer: usize) -> usize {
-        let mut total_cost = 0;
-        let mut from = b'A';
-        for &to in code {
-            total_cost += self.solve(from, to, layer);
-            from = to;
-        }
-
-        total_cost
+    pub fn keypads(&self) -> &[Keypad] {
+        &self.keypads
     }
 
-    fn solve(&self, from: u8, to: u8, layer: usize) -> usize {
-        if let Some(&cost) = self.cache.borrow().get(&(from, to, layer)) {
-            return cost;
+    /// Lazily reconstructs the keys pressed at `level` by one of the cheapest ways to type `code`.
+    ///
+    /// Level `0` are the keys pressed by hand, level `i > 0` the keys pressed on keypad `i - 1`.
+    pub fn sequence<'a>(&'a self, code: &str, level: usize) -> Sequence<'a, C> {
+        let top = self.keypads.len();
+        assert!(level <= top);
+        Sequence {
+            solver: self,
+            level,
+            stack: vec![(top, code.bytes().collect(), 0, b'A')],
         }
+    }
+}
 
-        const MAX_PATH_LENGTH: usize = 8usize;
+pub struct Sequence<'a, C> {
+    solver: &'a KeypadSolver<C>,
+    level: usize,
+    /// `(level, keys pressed at that level, next key index, current key of the robot arm)`
+    stack: Vec<(usize, Vec<u8>, usize, u8)>,
+}
 
-        // BFS to find all paths from->to
-        let mut min: Option<usize> = None;
-        let mut visited: FxHashSet<u8> = FxHashSet::default();
-        let mut q: VecDeque<_> = [(from, array_vec!([u8; MAX_PATH_LENGTH]))].into();
-        while let Some((cur, path)) = q.pop_front() {
-            if cur == to {
-                let mut path = path;
-                path.push(b'A');
-                let cost = if layer == 0 {
-                    path.len()
-                } else {
-                    // minimize lower layer
-                    self.solve_code_impl(&path, layer - 1)
-                };
-                if let Some(min) = &mut min {
-                    if cost < *min {
-                        *min = cost;
-                    }
-                } else {
-                    min = Some(cost)
-                }
+impl<C> Iterator for Sequence<'_, C> {
+    type Item = u8;
 
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (level, keys, i, arm) = self.stack.last_mut()?;
+            let Some(&key) = keys.get(*i) else {
+                self.stack.pop();
                 continue;
+            };
+
+            *i += 1;
+            if *level == self.level {
+                return Some(key);
             }
 
-            for dir in [b'^', b'v', b'>', b'<'] {
-                let Some(next) = (if layer < N {
-                    dpad_neighbor(cur, dir)
-                } else {
-                    numpad_neighbor(cur, dir)
-                }) else {
-                    continue;
-                };
-
-                if visited.contains(&next) {
-                    continue;
+            let lower = *level - 1;
+            let path = self.solver.tables[lower][&(*arm, key)].1.clone();
+            *arm = key;
+            self.stack.push((lower, path, 0, b'A'));
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum ReplayError {
+    #[error("press {press}: key {key:?} does not move a robot arm")]
+    NotADirection { press: usize, key: char },
+    #[error("press {press}: robot arm over keypad {keypad} points at a gap")]
+    Gap { press: usize, keypad: usize },
+}
+
+/// Executes keys pressed by hand through the whole stack of keypads and returns what is typed on
+/// the last one.
+pub fn replay(
+    keypads: &[Keypad],
+    presses: impl IntoIterator<Item = u8>,
+) -> Result<String, ReplayError> {
+    let mut arms = vec![b'A'; keypads.len()];
+    let mut typed = String::new();
+    for (press, mut key) in presses.into_iter().enumerate() {
+        let press = press + 1;
+        let mut keypad = 0;
+        loop {
+            if key == b'A' {
+                key = arms[keypad];
+                if keypad + 1 == keypads.len() {
+                    typed.push(key as char);
+                    break;
                 }
 
-                let mut path = path;
-                path.push(dir);
-                q.push_back((next, path));
+                keypad += 1;
+            } else if DIRECTIONS.contains(&key) {
+                let arm = &mut arms[keypad];
+                *arm = keypads[keypad]
+                    .neighbor(*arm, key)
+                    .ok_or(ReplayError::Gap { press, keypad })?;
+                break;
+            } else {
+                return Err(ReplayError::NotADirection {
+                    press,
+                    key: key as char,
+                });
             }
-
-            visited.insert(cur);
         }
-
-        let cost = min.unwrap();
-        self.cache.borrow_mut().insert((from, to, layer), cost);
-        cost
     }
+
+    Ok(typed)
 }
 
 #[aoc(day21, part1)]
 pub fn part1(input: &[String]) -> usize {
-    let c = CachingSolver::<2>::default();
-    c.solve_codes(input)
+    let c = KeypadSolver::<usize>::with_robots(2);
+    c.solve_codes(input).unwrap()
 }
 
 #[aoc(day21, part2)]
 pub fn part2(input: &[String]) -> usize {
-    let c = CachingSolver::<25>::default();
-    c.solve_codes(input)
+    let c = KeypadSolver::<usize>::with_robots(25);
+    c.solve_codes(input).unwrap()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use num::BigUint;
     use pretty_assertions::assert_eq;
 
     const INPUT: &str = r#"029A
@@ -255,61 +390,176 @@ mod tests {
 
     #[test]
     fn test_code_1_simple() {
-        let c = CachingSolver::<0>::default();
-        assert_eq!(c.solve_code("0"), 2);
+        let c = KeypadSolver::<usize>::with_robots(0);
+        assert_eq!(c.solve_code("0"), Some(2));
     }
 
     #[test]
     fn test_code_0() {
-        let c = CachingSolver::<0>::default();
-        assert_eq!(c.solve_code("029A"), 12);
+        let c = KeypadSolver::<usize>::with_robots(0);
+        assert_eq!(c.solve_code("029A"), Some(12));
     }
 
     #[test]
     fn test_code_0_simple() {
-        let c = CachingSolver::<1>::default();
-        assert_eq!(c.solve_code("0"), 8);
+        let c = KeypadSolver::<usize>::with_robots(1);
+        assert_eq!(c.solve_code("0"), Some(8));
     }
 
     #[test]
     fn test_code_1() {
-        let c = CachingSolver::<1>::default();
-        assert_eq!(c.solve_code("029A"), 28);
+        let c = KeypadSolver::<usize>::with_robots(1);
+        assert_eq!(c.solve_code("029A"), Some(28));
     }
 
     #[test]
     fn test_code_2_simple() {
-        let c = CachingSolver::<2>::default();
-        assert_eq!(c.solve_code("0"), 18);
+        let c = KeypadSolver::<usize>::with_robots(2);
+        assert_eq!(c.solve_code("0"), Some(18));
     }
 
     #[test]
     fn test_code_2_1() {
-        let c = CachingSolver::<2>::default();
-        assert_eq!(c.solve_code("029A"), 68);
+        let c = KeypadSolver::<usize>::with_robots(2);
+        assert_eq!(c.solve_code("029A"), Some(68));
     }
 
     #[test]
     fn test_code_2_2() {
-        let c = CachingSolver::<2>::default();
-        assert_eq!(c.solve_code("980A"), 60);
+        let c = KeypadSolver::<usize>::with_robots(2);
+        assert_eq!(c.solve_code("980A"), Some(60));
     }
 
     #[test]
     fn test_code_2_3() {
-        let c = CachingSolver::<2>::default();
-        assert_eq!(c.solve_code("179A"), 68);
+        let c = KeypadSolver::<usize>::with_robots(2);
+        assert_eq!(c.solve_code("179A"), Some(68));
     }
 
     #[test]
     fn test_code_2_4() {
-        let c = CachingSolver::<2>::default();
-        assert_eq!(c.solve_code("456A"), 64);
+        let c = KeypadSolver::<usize>::with_robots(2);
+        assert_eq!(c.solve_code("456A"), Some(64));
     }
 
     #[test]
     fn test_code_2_5() {
-        let c = CachingSolver::<2>::default();
-        assert_eq!(c.solve_code("379A"), 64);
+        let c = KeypadSolver::<usize>::with_robots(2);
+        assert_eq!(c.solve_code("379A"), Some(64));
+    }
+
+    #[test]
+    fn test_keypad_layout() {
+        let numpad = Keypad::numeric();
+        assert_eq!(numpad.neighbor(b'A', b'<'), Some(b'0'));
+        assert_eq!(numpad.neighbor(b'5', b'>'), Some(b'6'));
+        assert_eq!(numpad.neighbor(b'1', b'v'), None);
+        assert_eq!(numpad.neighbor(b'9', b'>'), None);
+
+        let dpad = Keypad::directional();
+        assert_eq!(dpad.neighbor(b'^', b'<'), None);
+        assert_eq!(dpad.neighbor(b'v', b'^'), Some(b'^'));
+        assert_eq!(
+            dpad.shortest_paths(b'A', b'<')
+                .into_iter()
+                .sorted()
+                .collect_vec(),
+            [b"<v<".to_vec(), b"v<<".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_custom_chain() {
+        let keypad: Keypad = "+---+---+---+\n| 1 | 2 | A |\n+---+---+---+"
+            .parse()
+            .unwrap();
+        let c = KeypadSolver::<usize>::new(vec![keypad.clone()]);
+        assert_eq!(c.solve_code("21A"), Some(7));
+
+        // <A<A>>A on the directional keypad
+        let c = KeypadSolver::<usize>::new(vec![Keypad::directional(), keypad]);
+        assert_eq!(c.solve_code("21A"), Some(21));
+    }
+
+    #[test]
+    fn test_sequence() {
+        let c = KeypadSolver::<usize>::with_robots(2);
+        for code in input_generator(INPUT) {
+            let presses = c.sequence(&code, 0).collect_vec();
+            assert_eq!(Some(presses.len()), c.solve_code(&code));
+            assert_eq!(replay(c.keypads(), presses).unwrap(), code);
+            for level in 1..=2 {
+                let presses = c.sequence(&code, level).collect_vec();
+                assert_eq!(replay(&c.keypads()[level..], presses).unwrap(), code);
+            }
+        }
+        assert_eq!(c.sequence("029A", 3).collect_vec(), b"029A");
+    }
+
+    #[test]
+    fn test_sequence_lazy() {
+        let c = KeypadSolver::<usize>::with_robots(25);
+        assert_eq!(c.sequence("029A", 0).take(1000).count(), 1000);
+
+        let presses = c.sequence("029A", 20).collect_vec();
+        assert_eq!(replay(&c.keypads()[20..], presses).unwrap(), "029A");
+    }
+
+    #[test]
+    fn test_replay() {
+        let keypads = KeypadSolver::<usize>::with_robots(2).keypads().to_vec();
+        assert_eq!(
+            replay(
+                &keypads,
+                "<vA<AA>>^AvAA<^A>A<v<A>>^AvA^A<vA>^A<v<A>^A>AAvA^A<v<A>A>^AAAvA<^A>A".bytes()
+            ),
+            Ok("029A".into())
+        );
+        assert_eq!(replay(&keypads[2..], "<A".bytes()), Ok("0".into()));
+        assert_eq!(
+            replay(&keypads[2..], "<<".bytes()),
+            Err(ReplayError::Gap {
+                press: 2,
+                keypad: 0
+            })
+        );
+        assert_eq!(
+            replay(&keypads[1..], "v<<AA".bytes()),
+            Err(ReplayError::Gap {
+                press: 5,
+                keypad: 1
+            })
+        );
+        assert_eq!(
+            replay(&keypads, "<1".bytes()),
+            Err(ReplayError::NotADirection { press: 2, key: '1' })
+        );
+    }
+
+    #[test]
+    fn test_cost_types() {
+        let codes = input_generator(INPUT);
+        let expected = KeypadSolver::<usize>::with_robots(25).solve_codes(&codes);
+        assert_eq!(
+            KeypadSolver::<BigUint>::with_robots(25).solve_codes(&codes),
+            expected.map(BigUint::from)
+        );
+
+        assert_eq!(
+            KeypadSolver::<u64>::with_robots(100).solve_code("029A"),
+            None
+        );
+        assert!(KeypadSolver::<u128>::with_robots(75)
+            .solve_code("029A")
+            .is_some());
+
+        let deep = KeypadSolver::<BigUint>::with_robots(1000)
+            .solve_code("029A")
+            .unwrap();
+        let deeper = KeypadSolver::<BigUint>::with_robots(1001)
+            .solve_code("029A")
+            .unwrap();
+        assert!(deep.bits() > 1000);
+        assert!(deeper > deep);
     }
 }
@@ -1,17 +1,23 @@
 use crate::common::parse_lines;
 use aoc_runner_derive::{aoc, aoc_generator};
-use itertools::{iproduct, Itertools};
+use indexmap::IndexSet;
+use itertools::Itertools;
 use pathfinding::prelude::topological_sort;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxBuildHasher, FxHashMap};
 use std::collections::hash_map::Entry;
-use std::ops::{BitAnd, BitOr, BitXor};
 use std::str::FromStr;
+use thiserror::Error;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum GateType {
+    Const(bool),
+    Not,
     And,
     Or,
     Xor,
+    Nand,
+    Nor,
+    Xnor,
 }
 
 impl FromStr for GateType {
@@ -19,32 +25,47 @@ impl FromStr for GateType {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
+            "0" => Ok(GateType::Const(false)),
+            "1" => Ok(GateType::Const(true)),
+            "NOT" => Ok(GateType::Not),
             "AND" => Ok(GateType::And),
             "OR" => Ok(GateType::Or),
             "XOR" => Ok(GateType::Xor),
+            "NAND" => Ok(GateType::Nand),
+            "NOR" => Ok(GateType::Nor),
+            "XNOR" => Ok(GateType::Xnor),
             _ => Err(()),
         }
     }
 }
 
 impl GateType {
-    pub fn eval<T: BitAnd<Output = R> + BitOr<Output = R> + BitXor<Output = R>, R>(
-        &self,
-        in1: T,
-        in2: T,
-    ) -> R {
+    pub fn accepts_arity(&self, n: usize) -> bool {
         match self {
-            GateType::And => in1 & in2,
-            GateType::Or => in1 | in2,
-            GateType::Xor => in1 ^ in2,
+            GateType::Const(_) => n == 0,
+            GateType::Not => n == 1,
+            _ => n >= 2,
+        }
+    }
+
+    pub fn eval(&self, inputs: impl IntoIterator<Item = bool>) -> bool {
+        let mut inputs = inputs.into_iter();
+        match self {
+            GateType::Const(value) => *value,
+            GateType::Not => !inputs.next().unwrap(),
+            GateType::And => inputs.all(|i| i),
+            GateType::Or => inputs.any(|i| i),
+            GateType::Xor => inputs.fold(false, |a, i| a ^ i),
+            GateType::Nand => !inputs.all(|i| i),
+            GateType::Nor => !inputs.any(|i| i),
+            GateType::Xnor => !inputs.fold(false, |a, i| a ^ i),
         }
     }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct Gate {
-    in1: String,
-    in2: String,
+    inputs: Vec<String>,
     out: String,
     op: GateType,
 }
@@ -52,27 +73,52 @@ pub struct Gate {
 impl FromStr for Gate {
     type Err = ();
 
+    /// Accepted forms are `a OP b [OP c ...] -> out` with a single repeated `OP`, `NOT a -> out`
+    /// and the constants `0 -> out` / `1 -> out`.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (in1, op, in2, _, out) = s.split_whitespace().collect_tuple().ok_or(())?;
+        let (lhs, out) = s.split_once("->").ok_or(())?;
+        let out = out.split_whitespace().exactly_one().map_err(|_| ())?;
+        let tokens = lhs.split_whitespace().collect_vec();
+        let (op, inputs) = match tokens.as_slice() {
+            [value] => (value.parse()?, vec![]),
+            ["NOT", in1] => (GateType::Not, vec![*in1]),
+            [in1, rest @ ..] if rest.len() % 2 == 0 => {
+                let op = rest.iter().step_by(2).all_equal_value().map_err(|_| ())?;
+                let op = op.parse()?;
+                (
+                    op,
+                    std::iter::once(*in1)
+                        .chain(rest.iter().skip(1).step_by(2).copied())
+                        .collect(),
+                )
+            }
+            _ => return Err(()),
+        };
+        if !op.accepts_arity(inputs.len()) {
+            return Err(());
+        }
+
         Ok(Self {
-            in1: in1.into(),
-            in2: in2.into(),
+            inputs: inputs.into_iter().map(Into::into).collect(),
             out: out.into(),
-            op: op.parse()?,
+            op,
         })
     }
 }
 
 impl Gate {
-    pub fn eval(&self, wires: &mut FxHashMap<String, bool>) -> Result<(), ()> {
-        let in1 = *wires.get(&self.in1).ok_or(())?;
-        let in2 = *wires.get(&self.in2).ok_or(())?;
+    pub fn eval(&self, wires: &mut FxHashMap<String, bool>) -> Result<(), SimulationError> {
+        if let Some(missing) = self.inputs.iter().find(|&i| !wires.contains_key(i)) {
+            return Err(SimulationError::Undriven(missing.clone()));
+        }
+
+        let value = self.op.eval(self.inputs.iter().map(|i| wires[i]));
         match wires.entry(self.out.clone()) {
             Entry::Occupied(_) => {
-                return Err(());
+                return Err(SimulationError::MultipleDrivers(self.out.clone()));
             }
             Entry::Vacant(e) => {
-                e.insert(self.op.eval(in1, in2));
+                e.insert(value);
             }
         }
         Ok(())
@@ -93,108 +139,508 @@ impl Gate {
 
     pub fn has_in(&self, in1: impl AsRef<str>) -> bool {
         let in1 = in1.as_ref();
-        self.in1 == in1 || self.in2 == in1
+        self.inputs.iter().any(|i| i == in1)
+    }
+
+    pub fn other_in(&self, in1: impl AsRef<str>) -> Option<&str> {
+        let in1 = in1.as_ref();
+        if self.inputs.len() == 2 && self.has_in(in1) {
+            self.inputs.iter().map(String::as_str).find(|&i| i != in1)
+        } else {
+            None
+        }
     }
 }
 
-#[aoc_generator(day24)]
-pub fn input_generator(input: &str) -> (FxHashMap<String, bool>, Vec<Gate>) {
-    let (wires, gates) = input.split_once("\n\n").unwrap();
-    (
-        wires
-            .lines()
-            .map(str::trim)
-            .filter(|l| !l.is_empty())
-            .map(|l| {
-                let (wire, value) = l.split_once(": ").unwrap();
-                (wire.into(), value.parse::<u8>().unwrap() != 0)
-            })
-            .collect(),
-        parse_lines(gates).unwrap(),
-    )
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum SimulationError {
+    #[error("wire {0} is driven by more than one gate")]
+    MultipleDrivers(String),
+    #[error("wire {0} has neither an initial value nor a driving gate")]
+    Undriven(String),
+    #[error("circuit oscillates with period {period} on wires {wires:?}")]
+    Oscillation { period: usize, wires: Vec<String> },
 }
 
-#[aoc(day24, part1)]
-pub fn part1((wires, gates): &(FxHashMap<String, bool>, Vec<Gate>)) -> u64 {
-    let sorted_gates = topological_sort(&gates.iter().collect_vec(), |&g| {
-        gates.iter().filter(|&c| c.in1 == g.out || c.in2 == g.out)
+/// Simulates a netlist that may contain feedback loops.
+///
+/// Gates are evaluated in topological order if there is one (so a DAG settles after a single
+/// pass), otherwise in the given order, updating wires in place until a full pass changes nothing.
+/// Initial values of gate outputs act as the starting state of latches, other gate outputs start
+/// at `false`. If a state repeats without settling, the wires that keep toggling are reported.
+pub fn simulate(
+    wires: &FxHashMap<String, bool>,
+    gates: &[Gate],
+) -> Result<FxHashMap<String, bool>, SimulationError> {
+    let mut names: IndexSet<&str, FxBuildHasher> = IndexSet::default();
+    let mut drivers = FxHashMap::default();
+    for gate in gates {
+        if drivers.insert(gate.out.as_str(), gate).is_some() {
+            return Err(SimulationError::MultipleDrivers(gate.out.clone()));
+        }
+    }
+    for name in wires
+        .keys()
+        .map(String::as_str)
+        .chain(
+            gates
+                .iter()
+                .flat_map(|g| g.inputs.iter().map(String::as_str)),
+        )
+        .chain(gates.iter().map(|g| g.out.as_str()))
+    {
+        if !wires.contains_key(name) && !drivers.contains_key(name) {
+            return Err(SimulationError::Undriven(name.into()));
+        }
+        names.insert(name);
+    }
+
+    let index = |name: &str| names.get_index_of(name).unwrap();
+    let order = topological_sort(&gates.iter().collect_vec(), |&g| {
+        gates.iter().filter(|&c| c.has_in(&g.out))
     })
-    .unwrap();
+    .unwrap_or_else(|_| gates.iter().collect());
+    let compiled = order
+        .into_iter()
+        .map(|g| {
+            (
+                g.op,
+                g.inputs.iter().map(|i| index(i)).collect_vec(),
+                index(&g.out),
+            )
+        })
+        .collect_vec();
 
-    let mut wires = wires.clone();
-    for gate in sorted_gates {
-        gate.eval(&mut wires).unwrap();
+    let mut state = names
+        .iter()
+        .map(|&n| wires.get(n).copied().unwrap_or(false))
+        .collect_vec();
+    let mut seen = FxHashMap::default();
+    for pass in 0.. {
+        if let Some(first) = seen.insert(state.clone(), pass) {
+            let mut toggling = vec![false; state.len()];
+            let mut current = state.clone();
+            for _ in first..pass {
+                let previous = current.clone();
+                for (op, inputs, out) in &compiled {
+                    current[*out] = op.eval(inputs.iter().map(|&i| current[i]));
+                }
+                toggling
+                    .iter_mut()
+                    .zip(previous.iter().zip(&current))
+                    .for_each(|(t, (a, b))| *t |= a != b);
+            }
+            return Err(SimulationError::Oscillation {
+                period: pass - first,
+                wires: names
+                    .iter()
+                    .zip(toggling)
+                    .filter(|(_, t)| *t)
+                    .map(|(&n, _)| n.into())
+                    .sorted_unstable()
+                    .collect(),
+            });
+        }
+
+        let mut changed = false;
+        for (op, inputs, out) in &compiled {
+            let value = op.eval(inputs.iter().map(|&i| state[i]));
+            if state[*out] != value {
+                state[*out] = value;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
     }
 
-    wires
+    Ok(names
         .iter()
-        .filter(|(n, _)| n.starts_with('z'))
-        .sorted_unstable_by_key(|(w, _)| w.as_str())
-        .rfold(0u64, |a, (_, &value)| (a << 1) | value as u64)
+        .zip(state)
+        .map(|(&n, v)| (n.into(), v))
+        .collect())
 }
 
-#[allow(dead_code)]
-fn check_adder(sorted_gates: &[&Gate], bit: usize, bit_amount: usize) -> Result<(), &'static str> {
-    assert!(bit < bit_amount);
-    for (x, y, carry) in iproduct!([false, true], [false, true], [false, true]) {
-        let mut wires = FxHashMap::default();
-        for i in 0..bit_amount {
-            wires.insert(
-                format!("x{i:02}"),
-                if i + 1 == bit {
-                    carry
-                } else if i == bit {
-                    x
-                } else {
-                    false
-                },
-            );
-            wires.insert(
-                format!("y{i:02}"),
-                if i + 1 == bit {
-                    carry
-                } else if i == bit {
-                    y
-                } else {
-                    false
-                },
-            );
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+struct BddNode {
+    var: usize,
+    lo: usize,
+    hi: usize,
+}
+
+/// Reduced ordered binary decision diagram, nodes `0` and `1` are the constants.
+#[derive(Debug)]
+struct Bdd {
+    nodes: Vec<BddNode>,
+    unique: FxHashMap<BddNode, usize>,
+    cache: FxHashMap<(GateType, usize, usize), usize>,
+}
+
+impl Bdd {
+    const FALSE: usize = 0;
+    const TRUE: usize = 1;
+
+    fn new() -> Self {
+        let terminal = |value| BddNode {
+            var: usize::MAX,
+            lo: value,
+            hi: value,
+        };
+        Self {
+            nodes: vec![terminal(Self::FALSE), terminal(Self::TRUE)],
+            unique: FxHashMap::default(),
+            cache: FxHashMap::default(),
+        }
+    }
+
+    fn constant(value: bool) -> usize {
+        if value {
+            Self::TRUE
+        } else {
+            Self::FALSE
         }
+    }
 
-        let mut wires = wires.clone();
-        for gate in sorted_gates {
-            gate.eval(&mut wires).unwrap();
+    fn node(&mut self, var: usize, lo: usize, hi: usize) -> usize {
+        if lo == hi {
+            return lo;
         }
 
-        let carry_enabled = carry && bit > 0;
-        if carry_enabled {
-            let prev = wires[&format!("z{:02}", bit - 1)];
-            if prev {
-                return Err("carry source (bit-1) should be 0");
+        let node = BddNode { var, lo, hi };
+        *self.unique.entry(node).or_insert_with(|| {
+            self.nodes.push(node);
+            self.nodes.len() - 1
+        })
+    }
+
+    fn var(&mut self, var: usize) -> usize {
+        self.node(var, Self::FALSE, Self::TRUE)
+    }
+
+    fn not(&mut self, a: usize) -> usize {
+        self.apply(GateType::Xor, a, Self::TRUE)
+    }
+
+    /// Combines two diagrams with one of the binary operators `And`, `Or` or `Xor`.
+    fn apply(&mut self, op: GateType, a: usize, b: usize) -> usize {
+        match (op, a, b) {
+            (_, Self::FALSE | Self::TRUE, Self::FALSE | Self::TRUE) => {
+                return Self::constant(op.eval([a == Self::TRUE, b == Self::TRUE]));
+            }
+            (GateType::And, Self::FALSE, _) | (GateType::And, _, Self::FALSE) => {
+                return Self::FALSE;
+            }
+            (GateType::Or, Self::TRUE, _) | (GateType::Or, _, Self::TRUE) => {
+                return Self::TRUE;
+            }
+            (GateType::And, Self::TRUE, x)
+            | (GateType::And, x, Self::TRUE)
+            | (GateType::Or | GateType::Xor, Self::FALSE, x)
+            | (GateType::Or | GateType::Xor, x, Self::FALSE) => {
+                return x;
             }
+            _ if a == b => {
+                return if op == GateType::Xor { Self::FALSE } else { a };
+            }
+            _ => {}
+        }
+
+        let key = (op, a.min(b), a.max(b));
+        if let Some(&result) = self.cache.get(&key) {
+            return result;
         }
-        let result = wires[&format!("z{bit:02}")];
-        let next = wires[&format!("z{:02}", bit + 1)];
-        if carry_enabled {
-            if result != (((x as u8 + y as u8 + 1) & 0x1) != 0) {
-                return Err("half-adder with carry-in is wrong");
+
+        let (na, nb) = (self.nodes[a], self.nodes[b]);
+        let var = na.var.min(nb.var);
+        let (a0, a1) = if na.var == var {
+            (na.lo, na.hi)
+        } else {
+            (a, a)
+        };
+        let (b0, b1) = if nb.var == var {
+            (nb.lo, nb.hi)
+        } else {
+            (b, b)
+        };
+        let lo = self.apply(op, a0, b0);
+        let hi = self.apply(op, a1, b1);
+        let result = self.node(var, lo, hi);
+        self.cache.insert(key, result);
+        result
+    }
+
+    fn gate(&mut self, op: GateType, inputs: &[usize]) -> usize {
+        let (base, negate) = match op {
+            GateType::Const(value) => return Self::constant(value),
+            GateType::Not => {
+                return self.not(inputs[0]);
             }
-            if next != (((x as u8 + y as u8 + 1) >> 1) & 0x1 != 0) {
-                return Err("carry-out with carry-in is wrong");
+            GateType::And | GateType::Or | GateType::Xor => (op, false),
+            GateType::Nand => (GateType::And, true),
+            GateType::Nor => (GateType::Or, true),
+            GateType::Xnor => (GateType::Xor, true),
+        };
+        let result = inputs[1..]
+            .iter()
+            .fold(inputs[0], |a, &b| self.apply(base, a, b));
+        if negate {
+            self.not(result)
+        } else {
+            result
+        }
+    }
+
+    /// Returns a satisfying assignment as `(var, value)` pairs, unlisted variables are arbitrary.
+    fn satisfy_one(&self, mut f: usize) -> Option<Vec<(usize, bool)>> {
+        if f == Self::FALSE {
+            return None;
+        }
+
+        let mut assignment = vec![];
+        while f != Self::TRUE {
+            let node = self.nodes[f];
+            if node.lo != Self::FALSE {
+                assignment.push((node.var, false));
+                f = node.lo;
+            } else {
+                assignment.push((node.var, true));
+                f = node.hi;
             }
+        }
+        Some(assignment)
+    }
+}
+
+/// Arithmetic functions of the `x` and `y` buses that a circuit's `z` bus can be checked against.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum BusFunction {
+    Add,
+    Sub,
+    And,
+}
+
+impl BusFunction {
+    pub fn eval(&self, x: u64, y: u64, out_width: usize) -> u64 {
+        let mask = if out_width >= 64 {
+            u64::MAX
         } else {
-            if result != (((x as u8 + y as u8) & 0x1) != 0) {
-                return Err("half-adder is wrong");
+            (1 << out_width) - 1
+        };
+        mask & match self {
+            BusFunction::Add => x.wrapping_add(y),
+            BusFunction::Sub => x.wrapping_sub(y),
+            BusFunction::And => x & y,
+        }
+    }
+
+    fn expected(&self, bdd: &mut Bdd, xs: &[usize], ys: &[usize], out_width: usize) -> Vec<usize> {
+        let bit = |bus: &[usize], i: usize| bus.get(i).copied().unwrap_or(Bdd::FALSE);
+        match self {
+            BusFunction::Add | BusFunction::Sub => {
+                // x - y is computed as x + !y + 1
+                let sub = *self == BusFunction::Sub;
+                let mut carry = Bdd::constant(sub);
+                (0..out_width)
+                    .map(|i| {
+                        let x = bit(xs, i);
+                        let y = bit(ys, i);
+                        let y = if sub { bdd.not(y) } else { y };
+                        let half = bdd.apply(GateType::Xor, x, y);
+                        let sum = bdd.apply(GateType::Xor, half, carry);
+                        let carry1 = bdd.apply(GateType::And, x, y);
+                        let carry2 = bdd.apply(GateType::And, half, carry);
+                        carry = bdd.apply(GateType::Or, carry1, carry2);
+                        sum
+                    })
+                    .collect()
+            }
+            BusFunction::And => (0..out_width)
+                .map(|i| bdd.apply(GateType::And, bit(xs, i), bit(ys, i)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum EquivalenceError {
+    #[error("circuit contains a feedback loop")]
+    Cyclic,
+    #[error("wire {0} is neither a bus input nor driven by a gate")]
+    Undriven(String),
+    #[error("wire {0} is driven by more than one gate")]
+    MultipleDrivers(String),
+    #[error("for x={x} and y={y} the circuit computes {actual} instead of {expected}")]
+    Counterexample {
+        x: u64,
+        y: u64,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("output {0} differs from the expected function, but the buses are too wide for a counterexample")]
+    Mismatch(String),
+}
+
+fn bus_index(name: &str, bus: char) -> Option<usize> {
+    name.strip_prefix(bus)?.parse().ok()
+}
+
+/// Exchanges the output wires of the given pairs of gates.
+pub fn swap_outputs(gates: &[Gate], swaps: &[(&str, &str)]) -> Vec<Gate> {
+    gates
+        .iter()
+        .cloned()
+        .map(|mut g| {
+            for &(a, b) in swaps {
+                if g.out == a {
+                    g.out = b.into();
+                } else if g.out == b {
+                    g.out = a.into();
+                }
+            }
+            g
+        })
+        .collect()
+}
+
+/// Proves that the `z` bus of a combinational circuit equals `function` applied to its `x` and `y`
+/// buses for all inputs, or finds an input where it doesn't.
+///
+/// Every gate output is represented as a BDD with the input bits interleaved as
+/// `x00 < y00 < x01 < y01 < ...`, which keeps adders linear in size.
+/// Counterexamples are only given for buses of at most 64 bits, wider ones report the first wrong output.
+pub fn check_equivalence(gates: &[Gate], function: BusFunction) -> Result<(), EquivalenceError> {
+    let order = topological_sort(&gates.iter().collect_vec(), |&g| {
+        gates.iter().filter(|&c| c.has_in(&g.out))
+    })
+    .map_err(|_| EquivalenceError::Cyclic)?;
+
+    let mut buses: [Vec<Option<&str>>; 3] = Default::default();
+    let mut drivers = FxHashMap::default();
+    for gate in gates {
+        if drivers.insert(gate.out.as_str(), gate).is_some() {
+            return Err(EquivalenceError::MultipleDrivers(gate.out.clone()));
+        }
+    }
+    for name in gates
+        .iter()
+        .flat_map(|g| g.inputs.iter().chain([&g.out]))
+        .map(String::as_str)
+    {
+        for (bus, names) in ['x', 'y', 'z'].into_iter().zip(&mut buses) {
+            if let Some(i) = bus_index(name, bus) {
+                if names.len() <= i {
+                    names.resize(i + 1, None);
+                }
+                names[i] = Some(name);
             }
-            if next != (((x as u8 + y as u8) >> 1) & 0x1 != 0) {
-                return Err("carry-out to next bit is wrong");
+        }
+    }
+    let [x_names, y_names, z_names] = buses;
+    let in_width = x_names.len().max(y_names.len());
+
+    let mut bdd = Bdd::new();
+    let mut values = FxHashMap::default();
+    let mut xs = vec![Bdd::FALSE; in_width];
+    let mut ys = vec![Bdd::FALSE; in_width];
+    for (offset, names, vars) in [(0, &x_names, &mut xs), (1, &y_names, &mut ys)] {
+        for (i, name) in names.iter().enumerate() {
+            if let Some(name) = name {
+                vars[i] = bdd.var(2 * i + offset);
+                values.insert(*name, vars[i]);
             }
         }
     }
 
+    for gate in order {
+        let inputs = gate
+            .inputs
+            .iter()
+            .map(|i| {
+                values
+                    .get(i.as_str())
+                    .copied()
+                    .ok_or_else(|| EquivalenceError::Undriven(i.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let value = bdd.gate(gate.op, &inputs);
+        values.insert(gate.out.as_str(), value);
+    }
+
+    let actual = z_names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            name.filter(|name| drivers.contains_key(name))
+                .map(|name| values[name])
+                .ok_or_else(|| EquivalenceError::Undriven(format!("z{i:02}")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let expected = function.expected(&mut bdd, &xs, &ys, z_names.len());
+    for (i, (&actual, &expected)) in actual.iter().zip(&expected).enumerate() {
+        let diff = bdd.apply(GateType::Xor, actual, expected);
+        if let Some(assignment) = bdd.satisfy_one(diff) {
+            if in_width > 64 || z_names.len() > 64 {
+                return Err(EquivalenceError::Mismatch(z_names[i].unwrap().to_string()));
+            }
+
+            let (mut x, mut y) = (0, 0);
+            for (var, value) in assignment {
+                let bus = if var % 2 == 0 { &mut x } else { &mut y };
+                *bus |= (value as u64) << (var / 2);
+            }
+
+            let mut wires = FxHashMap::default();
+            for (bus, names) in [(x, &x_names), (y, &y_names)] {
+                for (i, name) in names.iter().enumerate() {
+                    if let Some(name) = name {
+                        wires.insert(name.to_string(), (bus >> i) & 1 != 0);
+                    }
+                }
+            }
+            let wires = simulate(&wires, gates).unwrap();
+            let actual = z_names
+                .iter()
+                .rfold(0u64, |a, name| (a << 1) | wires[name.unwrap()] as u64);
+            return Err(EquivalenceError::Counterexample {
+                x,
+                y,
+                expected: function.eval(x, y, z_names.len()),
+                actual,
+            });
+        }
+    }
+
     Ok(())
 }
 
+#[aoc_generator(day24)]
+pub fn input_generator(input: &str) -> (FxHashMap<String, bool>, Vec<Gate>) {
+    let (wires, gates) = input.split_once("\n\n").unwrap();
+    (
+        wires
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(|l| {
+                let (wire, value) = l.split_once(": ").unwrap();
+                (wire.into(), value.parse::<u8>().unwrap() != 0)
+            })
+            .collect(),
+        parse_lines(gates).unwrap(),
+    )
+}
+
+#[aoc(day24, part1)]
+pub fn part1((wires, gates): &(FxHashMap<String, bool>, Vec<Gate>)) -> u64 {
+    simulate(wires, gates)
+        .unwrap()
+        .iter()
+        .filter(|(n, _)| n.starts_with('z'))
+        .sorted_unstable_by_key(|(w, _)| w.as_str())
+        .rfold(0u64, |a, (_, &value)| (a << 1) | value as u64)
+}
+
 #[aoc(day24, part2)]
 pub fn part2((wires, gates): &(FxHashMap<String, bool>, Vec<Gate>)) -> String {
     println!(
@@ -206,7 +652,7 @@ pub fn part2((wires, gates): &(FxHashMap<String, bool>, Vec<Gate>)) -> String {
             .chain(
                 gates
                     .iter()
-                    .flat_map(|g| [g.in1.as_str(), g.in2.as_str(), g.out.as_str()])
+                    .flat_map(|g| g.inputs.iter().chain([&g.out]).map(String::as_str))
             )
             .unique()
             .count()
@@ -282,14 +728,10 @@ pub fn part2((wires, gates): &(FxHashMap<String, bool>, Vec<Gate>)) -> String {
             if let (Some(xor2), Some(xor2_from_out), Some(carry2), &[or]) =
                 (xor2, xor2_from_out, carry2, or.as_slice())
             {
-                let carry_in = if xor2.in1 == xor1.out {
-                    Some(xor2.in2.as_str())
-                } else if xor2.in2 == xor1.out {
-                    Some(xor2.in1.as_str())
-                } else {
+                let carry_in = xor2.other_in(&xor1.out);
+                if carry_in.is_none() {
                     println!("  incorrect carry-in input in xor2: {xor2:?}");
-                    None
-                };
+                }
                 if let Some(carry_in) = carry_in {
                     if !carry2.has_in(carry_in) {
                         println!("  incorrect carry-in input in carry2: {carry2:?}");
@@ -337,15 +779,17 @@ pub fn part2((wires, gates): &(FxHashMap<String, bool>, Vec<Gate>)) -> String {
         }
     }
 
-    [
-        "qff", "qnw", // bit 11, outputs of first half-adder switched around
-        "pbv", "z16", // switched output
-        "qqp", "z23", // switched output
-        "fbq", "z36", // switched output
-    ]
-    .into_iter()
-    .sorted_unstable()
-    .join(",")
+    let swaps = [
+        ("qff", "qnw"), // bit 11, outputs of first half-adder switched around
+        ("pbv", "z16"), // switched output
+        ("qqp", "z23"), // switched output
+        ("fbq", "z36"), // switched output
+    ];
+    swaps
+        .into_iter()
+        .flat_map(|(a, b)| [a, b])
+        .sorted_unstable()
+        .join(",")
 }
 
 #[cfg(test)]
@@ -420,4 +864,204 @@ tnw OR pbm -> gnj"#;
     fn test_part1_2() {
         assert_eq!(part1(&input_generator(INPUT_2)), 2024);
     }
+
+    #[test]
+    fn test_extended_gates() {
+        const INPUT: &str = r#"x00: 1
+x01: 0
+
+NOT x00 -> z00
+x00 NAND x01 -> z01
+x00 NOR x01 -> z02
+x00 XNOR x01 -> z03
+x00 AND one AND x01 -> z04
+x01 XOR one XOR x01 -> z05
+1 -> one
+0 -> z06"#;
+        assert_eq!(part1(&input_generator(INPUT)), 0b0100010);
+    }
+
+    #[test]
+    fn test_invalid_gates() {
+        assert!("x00 AND y00 OR z00 -> out".parse::<Gate>().is_err());
+        assert!("NOT x00 y00 -> out".parse::<Gate>().is_err());
+        assert!("x00 NOT y00 -> out".parse::<Gate>().is_err());
+        assert!("x00 -> out".parse::<Gate>().is_err());
+        assert!("x00 AND y00 -> a b".parse::<Gate>().is_err());
+    }
+
+    #[test]
+    fn test_latch() {
+        const INPUT: &str = r#"s: 0
+r: 0
+q: 1
+
+r NOR qn -> q
+s NOR q -> qn"#;
+        let (mut wires, gates) = input_generator(INPUT);
+        let result = simulate(&wires, &gates).unwrap();
+        assert_eq!((result["q"], result["qn"]), (true, false));
+
+        wires.insert("r".into(), true);
+        let result = simulate(&wires, &gates).unwrap();
+        assert_eq!((result["q"], result["qn"]), (false, true));
+
+        wires.insert("q".into(), false);
+        wires.insert("r".into(), false);
+        wires.insert("s".into(), true);
+        let result = simulate(&wires, &gates).unwrap();
+        assert_eq!((result["q"], result["qn"]), (true, false));
+    }
+
+    #[test]
+    fn test_oscillation() {
+        const INPUT: &str = r#"en: 1
+
+en AND b -> a
+NOT a -> b"#;
+        let (wires, gates) = input_generator(INPUT);
+        assert_eq!(
+            simulate(&wires, &gates),
+            Err(SimulationError::Oscillation {
+                period: 2,
+                wires: vec!["a".into(), "b".into()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_simulation_errors() {
+        let (wires, gates) = input_generator("x00: 1\n\nx00 AND y00 -> z00");
+        assert_eq!(
+            simulate(&wires, &gates),
+            Err(SimulationError::Undriven("y00".into()))
+        );
+
+        let (wires, gates) = input_generator("x00: 1\n\nNOT x00 -> z00\n1 -> z00");
+        assert_eq!(
+            simulate(&wires, &gates),
+            Err(SimulationError::MultipleDrivers("z00".into()))
+        );
+    }
+
+    /// Ripple-carry adder (or subtractor computing `x + !y + 1`) in the puzzle's wire naming.
+    fn ripple_carry(bits: usize, sub: bool) -> Vec<Gate> {
+        let mut gates = vec![];
+        let mut carry = "cin".to_string();
+        gates.push(format!("{} -> cin", sub as u8));
+        for i in 0..bits {
+            let y = if sub {
+                gates.push(format!("NOT y{i:02} -> ny{i:02}"));
+                format!("ny{i:02}")
+            } else {
+                format!("y{i:02}")
+            };
+            gates.push(format!("x{i:02} XOR {y} -> h{i:02}"));
+            gates.push(format!("h{i:02} XOR {carry} -> z{i:02}"));
+            gates.push(format!("x{i:02} AND {y} -> a{i:02}"));
+            gates.push(format!("h{i:02} AND {carry} -> b{i:02}"));
+            carry = if i + 1 == bits && !sub {
+                format!("z{bits:02}")
+            } else {
+                format!("c{i:02}")
+            };
+            gates.push(format!("a{i:02} OR b{i:02} -> {carry}"));
+        }
+        if sub {
+            // the borrow is the inverted carry
+            gates.push(format!("NOT {carry} -> z{bits:02}"));
+        }
+        gates.iter().map(|g| g.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_equivalence_adder() {
+        assert_eq!(
+            check_equivalence(&ripple_carry(8, false), BusFunction::Add),
+            Ok(())
+        );
+        assert_eq!(
+            check_equivalence(&ripple_carry(45, false), BusFunction::Add),
+            Ok(())
+        );
+        assert_eq!(
+            check_equivalence(&ripple_carry(16, true), BusFunction::Sub),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_equivalence_counterexample() {
+        let gates = swap_outputs(&ripple_carry(45, false), &[("z20", "b20")]);
+        let Err(EquivalenceError::Counterexample {
+            x,
+            y,
+            expected,
+            actual,
+        }) = check_equivalence(&gates, BusFunction::Add)
+        else {
+            panic!("expected a counterexample");
+        };
+        assert_eq!(expected, x + y);
+        assert_ne!(expected, actual);
+
+        let mut wires = FxHashMap::default();
+        for i in 0..45 {
+            wires.insert(format!("x{i:02}"), (x >> i) & 1 != 0);
+            wires.insert(format!("y{i:02}"), (y >> i) & 1 != 0);
+        }
+        assert_eq!(part1(&(wires, gates)), actual);
+    }
+
+    #[test]
+    fn test_equivalence_swaps() {
+        let swaps = [("z05", "b05"), ("h17", "a17"), ("a30", "z30")];
+        let gates = swap_outputs(&ripple_carry(45, false), &swaps);
+        assert!(matches!(
+            check_equivalence(&gates, BusFunction::Add),
+            Err(EquivalenceError::Counterexample { .. })
+        ));
+        assert_eq!(
+            check_equivalence(&swap_outputs(&gates, &swaps), BusFunction::Add),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_equivalence_wide() {
+        assert_eq!(
+            check_equivalence(&ripple_carry(70, false), BusFunction::Add),
+            Ok(())
+        );
+        let gates = swap_outputs(&ripple_carry(70, false), &[("z66", "b66")]);
+        assert_eq!(
+            check_equivalence(&gates, BusFunction::Add),
+            Err(EquivalenceError::Mismatch("z66".into()))
+        );
+    }
+
+    #[test]
+    fn test_equivalence_and() {
+        let gates = (0..6)
+            .map(|i| format!("x{i:02} AND y{i:02} -> z{i:02}").parse().unwrap())
+            .collect_vec();
+        assert_eq!(check_equivalence(&gates, BusFunction::And), Ok(()));
+        assert!(matches!(
+            check_equivalence(&gates, BusFunction::Add),
+            Err(EquivalenceError::Counterexample { .. })
+        ));
+        assert!(matches!(
+            check_equivalence(&ripple_carry(4, false), BusFunction::Sub),
+            Err(EquivalenceError::Counterexample { .. })
+        ));
+    }
+
+    #[test]
+    fn test_equivalence_cyclic() {
+        let (_, gates) = input_generator("x00: 1\n\nx00 AND z00 -> a\nNOT a -> z00");
+        assert_eq!(
+            check_equivalence(&gates, BusFunction::And),
+            Err(EquivalenceError::Cyclic)
+        );
+    }
 }